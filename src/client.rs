@@ -0,0 +1,268 @@
+//! Off-chain helpers for integrators, kept behind the `client` feature so they stay out of
+//! the on-chain program.
+
+use crate::instruction::ed25519_program;
+use crate::state::{find_mailbox_address, Mail};
+use borsh::BorshSerialize;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use solana_program::{
+  borsh::get_instance_packed_len,
+  instruction::{AccountMeta, Instruction},
+  pubkey::Pubkey,
+  rent::Rent,
+  system_program,
+  sysvar::instructions,
+};
+use std::io::{self, Read, Write};
+
+/// Build an `InitAccount` instruction creating the mailbox derived from `owner`, who signs
+/// and pays its rent.
+pub fn init_account_ix(program_id: &Pubkey, owner: &Pubkey) -> Instruction {
+  let (mailbox, _) = find_mailbox_address(owner, program_id);
+
+  Instruction {
+    program_id: *program_id,
+    accounts: vec![
+      AccountMeta::new(mailbox, false),
+      AccountMeta::new(*owner, true),
+      AccountMeta::new_readonly(system_program::id(), false),
+    ],
+    data: vec![0],
+  }
+}
+
+/// Build a `SendMail` instruction from the `sender` mailbox to the `receiver` mailbox, signed
+/// by `owner`, the sender's owner wallet. `nonce` must be the sender's current `send_nonce`.
+pub fn send_mail_ix(
+  program_id: &Pubkey,
+  sender: &Pubkey,
+  owner: &Pubkey,
+  receiver: &Pubkey,
+  mail: &Mail,
+  nonce: u64,
+) -> Instruction {
+  let mut data = vec![1];
+  data.extend((mail, false, nonce, false, false).try_to_vec().unwrap());
+
+  Instruction {
+    program_id: *program_id,
+    accounts: vec![
+      AccountMeta::new(*sender, false),
+      AccountMeta::new(*receiver, false),
+      AccountMeta::new_readonly(*owner, true),
+    ],
+    data,
+  }
+}
+
+/// Build a `SendMail` instruction with `verify_signature` set. Place it after
+/// [verify_signature_ix](fn.verify_signature_ix.html) in the same transaction.
+pub fn send_signed_mail_ix(
+  program_id: &Pubkey,
+  sender: &Pubkey,
+  owner: &Pubkey,
+  receiver: &Pubkey,
+  mail: &Mail,
+  nonce: u64,
+) -> Instruction {
+  let mut data = vec![1];
+  data.extend((mail, false, nonce, true, false).try_to_vec().unwrap());
+
+  Instruction {
+    program_id: *program_id,
+    accounts: vec![
+      AccountMeta::new(*sender, false),
+      AccountMeta::new(*receiver, false),
+      AccountMeta::new_readonly(*owner, true),
+      AccountMeta::new_readonly(instructions::id(), false),
+    ],
+    data,
+  }
+}
+
+/// Build the Ed25519 program instruction checking `mail.signature` by `signer`, the sending
+/// mailbox's owner wallet, over `Mail::signing_message`, with the signature, key and message
+/// inline.
+pub fn verify_signature_ix(signer: &Pubkey, mail: &Mail) -> Instruction {
+  let message = mail.signing_message();
+  let signature_offset: u16 = 16;
+  let public_key_offset = signature_offset + mail.signature.len() as u16;
+  let message_offset = public_key_offset + 32;
+
+  let mut data = vec![1, 0];
+  for value in &[
+    signature_offset,
+    u16::MAX,
+    public_key_offset,
+    u16::MAX,
+    message_offset,
+    message.len() as u16,
+    u16::MAX,
+  ] {
+    data.extend_from_slice(&value.to_le_bytes());
+  }
+  data.extend_from_slice(&mail.signature);
+  data.extend_from_slice(signer.as_ref());
+  data.extend_from_slice(&message);
+
+  Instruction {
+    program_id: ed25519_program::id(),
+    accounts: vec![],
+    data,
+  }
+}
+
+/// The marginal rent for the bytes `mail` takes up once stored, without the per-account
+/// overhead. Mailboxes are allocated at a fixed `MAILBOX_LEN` and never resized, so storing
+/// the mail does not change the balance a mailbox needs; this is the share of that rent the
+/// mail's bytes account for. Sending stores a copy in both the sender's and the receiver's
+/// mailbox.
+pub fn estimate_send_rent(mail: &Mail) -> u64 {
+  let rent = Rent::default();
+  let delta_bytes = get_instance_packed_len(mail).unwrap();
+
+  // `minimum_balance` includes the per-account overhead, which the mailbox already pays.
+  rent.minimum_balance(delta_bytes) - rent.minimum_balance(0)
+}
+
+/// Deflate a mail body and base64 encode the result so it can be stored in the `body` of a
+/// mail sent with `compressed` set.
+pub fn compress_body(body: &str) -> io::Result<String> {
+  let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+  encoder.write_all(body.as_bytes())?;
+
+  Ok(base64::encode(encoder.finish()?))
+}
+
+/// Reverse [compress_body](fn.compress_body.html), returning the original text.
+pub fn decompress_body(body: &str) -> io::Result<String> {
+  let compressed =
+    base64::decode(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+  let mut decompressed = String::new();
+  DeflateDecoder::new(&compressed[..]).read_to_string(&mut decompressed)?;
+
+  Ok(decompressed)
+}
+
+// Sanity tests
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::instruction::MailInstruction;
+
+  #[test]
+  fn test_init_account_ix() {
+    let program_id = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+
+    let instruction = init_account_ix(&program_id, &owner);
+
+    assert_eq!(instruction.program_id, program_id);
+    assert_eq!(
+      instruction.accounts,
+      vec![
+        AccountMeta::new(find_mailbox_address(&owner, &program_id).0, false),
+        AccountMeta::new(owner, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+      ]
+    );
+    assert_eq!(
+      MailInstruction::unpack(&instruction.data).unwrap(),
+      MailInstruction::InitAccount {
+        send_welcome: false,
+        welcome_subject: None,
+        welcome_body: None,
+      }
+    );
+  }
+
+  #[test]
+  fn test_send_mail_ix() {
+    let program_id = Pubkey::new_unique();
+    let sender = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let receiver = Pubkey::new_unique();
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender.to_string(),
+      to_address: receiver.to_string(),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let instruction = send_mail_ix(&program_id, &sender, &owner, &receiver, &mail, 3);
+
+    assert_eq!(instruction.program_id, program_id);
+    assert_eq!(
+      instruction.accounts,
+      vec![
+        AccountMeta::new(sender, false),
+        AccountMeta::new(receiver, false),
+        AccountMeta::new_readonly(owner, true),
+      ]
+    );
+    assert_eq!(instruction.data[0], 1);
+    assert_eq!(
+      MailInstruction::unpack(&instruction.data).unwrap(),
+      MailInstruction::SendMail {
+        mail,
+        require_receiver_signature: false,
+        nonce: 3,
+        verify_signature: false,
+        normalize_subject: false,
+      }
+    );
+  }
+
+  #[test]
+  fn test_estimate_send_rent() {
+    let short = Mail {
+      id: String::from("1"),
+      subject: String::from("Hi"),
+      body: String::from("Short"),
+      ..Mail::default()
+    };
+    let long = Mail {
+      body: "a".repeat(1_000),
+      ..short.clone()
+    };
+
+    let short_rent = estimate_send_rent(&short);
+    let long_rent = estimate_send_rent(&long);
+
+    assert!(short_rent > 0);
+    assert!(long_rent > short_rent);
+    assert_eq!(
+      long_rent - short_rent,
+      Rent::default().minimum_balance(995) - Rent::default().minimum_balance(0)
+    );
+  }
+
+  #[test]
+  fn test_compress_round_trip() {
+    let body = "Lorem, ipsum dolor sit amet consectetur adipisicing elit. ".repeat(20);
+
+    let compressed = compress_body(&body).unwrap();
+
+    assert!(compressed.len() < body.len());
+    assert_eq!(decompress_body(&compressed).unwrap(), body);
+  }
+
+  #[test]
+  fn test_compress_round_trip_multibyte() {
+    let body = "Grüße aus München 👋";
+
+    assert_eq!(
+      decompress_body(&compress_body(body).unwrap()).unwrap(),
+      body
+    );
+    assert_eq!(decompress_body(&compress_body("").unwrap()).unwrap(), "");
+  }
+
+  #[test]
+  fn test_decompress_invalid() {
+    assert!(decompress_body("not base64!").is_err());
+    assert!(decompress_body(&base64::encode("not deflate")).is_err());
+  }
+}
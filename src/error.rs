@@ -1,14 +1,145 @@
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
-#[derive(Error, Debug, Copy, Clone)]
+/// Errors surfaced to clients as `ProgramError::Custom(code)`. The codes are part of the public
+/// ABI, so every variant carries an explicit discriminant and new variants go at the end.
+#[derive(Error, Debug, Copy, Clone, PartialEq)]
 pub enum MailError {
   /// Invalid Instruction
   #[error("Invalid Instruction")]
-  InvalidInstruction,
+  InvalidInstruction = 0,
   /// Account Is Not Writable
   #[error("Account Is Not Writable")]
-  NotWritable,
+  NotWritable = 1,
+  /// Mail Id Does Not Match
+  #[error("Mail Id Does Not Match")]
+  MailIdMismatch = 2,
+  /// Mail Not Found
+  #[error("Mail Not Found")]
+  MailNotFound = 3,
+  /// Invalid Priority
+  #[error("Invalid Priority")]
+  InvalidPriority = 4,
+  /// Account Data Too Small
+  #[error("Account Data Too Small")]
+  AccountTooSmall = 5,
+  /// Invalid Address
+  #[error("Invalid Address")]
+  InvalidAddress = 6,
+  /// Contact Alias Already Exists
+  #[error("Contact Alias Already Exists")]
+  DuplicateContact = 7,
+  /// Contact Not Found
+  #[error("Contact Not Found")]
+  ContactNotFound = 8,
+  /// Mail Has No Subject Or Body
+  #[error("Mail Has No Subject Or Body")]
+  EmptyMessage = 9,
+  /// Too Many Labels
+  #[error("Too Many Labels")]
+  TooManyLabels = 10,
+  /// Receiver Must Sign To Accept Mail
+  #[error("Receiver Must Sign To Accept Mail")]
+  ReceiverConsentRequired = 11,
+  /// Compressed Mail Has No Body
+  #[error("Compressed Mail Has No Body")]
+  EmptyCompressedBody = 12,
+  /// Undo Window Expired
+  #[error("Undo Window Expired")]
+  UndoWindowExpired = 13,
+  /// Unauthorized
+  #[error("Unauthorized")]
+  Unauthorized = 14,
+  /// Too Many Attachments
+  #[error("Too Many Attachments")]
+  TooManyAttachments = 15,
+  /// Attachment URI Too Long
+  #[error("Attachment URI Too Long")]
+  AttachmentUriTooLong = 16,
+  /// Account And Mail Counts Do Not Match
+  #[error("Account And Mail Counts Do Not Match")]
+  AccountMailCountMismatch = 17,
+  /// Index Out Of Bounds
+  #[error("Index Out Of Bounds")]
+  IndexOutOfBounds = 18,
+  /// Group Members Do Not Match Accounts
+  #[error("Group Members Do Not Match Accounts")]
+  GroupMemberMismatch = 19,
+  /// Account Not Initialized
+  #[error("Account Not Initialized")]
+  AccountNotInitialized = 20,
+  /// Invalid Nonce
+  #[error("Invalid Nonce")]
+  InvalidNonce = 21,
+  /// Receiver Not Initialized
+  #[error("Receiver Not Initialized")]
+  ReceiverNotInitialized = 22,
+  /// Quota Exceeded
+  #[error("Quota Exceeded")]
+  QuotaExceeded = 23,
+  /// Mail Already Read
+  #[error("Mail Already Read")]
+  AlreadyRead = 24,
+  /// Mailbox Address Does Not Match Owner
+  #[error("Mailbox Address Does Not Match Owner")]
+  InvalidMailboxAddress = 25,
+  /// Too Many Recipients
+  #[error("Too Many Recipients")]
+  TooManyRecipients = 26,
+  /// Subject Too Long
+  #[error("Subject Too Long")]
+  SubjectTooLong = 27,
+  /// Sender Not Allowlisted
+  #[error("Sender Not Allowlisted")]
+  SenderNotAllowlisted = 28,
+  /// Arithmetic Overflow
+  #[error("Arithmetic Overflow")]
+  ArithmeticOverflow = 29,
+  /// Invalid Account Data
+  #[error("Invalid Account Data")]
+  InvalidAccountData = 30,
+  /// Mail Signature Does Not Verify
+  #[error("Mail Signature Does Not Verify")]
+  InvalidSignature = 31,
+  /// Account Not Assigned To Program
+  #[error("Account Not Assigned To Program")]
+  AccountNotAssigned = 32,
+  /// Instruction Data Is Empty
+  #[error("Instruction Data Is Empty")]
+  EmptyInstructionData = 33,
+  /// Not Enough Accounts
+  #[error("Not Enough Accounts")]
+  NotEnoughAccounts = 34,
+  /// Account Data Serialization Failed
+  #[error("Account Data Serialization Failed")]
+  SerializationFailed = 35,
+  /// Display Name Too Long
+  #[error("Display Name Too Long")]
+  DisplayNameTooLong = 36,
+  /// Avatar URI Too Long
+  #[error("Avatar URI Too Long")]
+  AvatarUriTooLong = 37,
+  /// Too Many Reactions
+  #[error("Too Many Reactions")]
+  TooManyReactions = 38,
+  /// Mail Not Addressed To Receiver
+  #[error("Mail Not Addressed To Receiver")]
+  RecipientMismatch = 39,
+  /// Participants Do Not Match Accounts
+  #[error("Participants Do Not Match Accounts")]
+  ParticipantMismatch = 40,
+  /// Account Already Initialized
+  #[error("Account Already Initialized")]
+  AlreadyInitialized = 41,
+  /// Draft Already Exists
+  #[error("Draft Already Exists")]
+  DuplicateDraft = 42,
+  /// Mail Not From Sender
+  #[error("Mail Not From Sender")]
+  SenderMismatch = 43,
+  /// Mail Id Already In Folder
+  #[error("Mail Id Already In Folder")]
+  DuplicateMailId = 44,
 }
 
 impl From<MailError> for ProgramError {
@@ -16,3 +147,86 @@ impl From<MailError> for ProgramError {
     ProgramError::Custom(e as u32)
   }
 }
+
+// Sanity tests
+#[cfg(test)]
+mod test {
+  use super::MailError::*;
+  use super::*;
+  use std::collections::BTreeSet;
+
+  /// Every variant with the code clients see.
+  const CODES: &[(MailError, u32)] = &[
+    (InvalidInstruction, 0),
+    (NotWritable, 1),
+    (MailIdMismatch, 2),
+    (MailNotFound, 3),
+    (InvalidPriority, 4),
+    (AccountTooSmall, 5),
+    (InvalidAddress, 6),
+    (DuplicateContact, 7),
+    (ContactNotFound, 8),
+    (EmptyMessage, 9),
+    (TooManyLabels, 10),
+    (ReceiverConsentRequired, 11),
+    (EmptyCompressedBody, 12),
+    (UndoWindowExpired, 13),
+    (Unauthorized, 14),
+    (TooManyAttachments, 15),
+    (AttachmentUriTooLong, 16),
+    (AccountMailCountMismatch, 17),
+    (IndexOutOfBounds, 18),
+    (GroupMemberMismatch, 19),
+    (AccountNotInitialized, 20),
+    (InvalidNonce, 21),
+    (ReceiverNotInitialized, 22),
+    (QuotaExceeded, 23),
+    (AlreadyRead, 24),
+    (InvalidMailboxAddress, 25),
+    (TooManyRecipients, 26),
+    (SubjectTooLong, 27),
+    (SenderNotAllowlisted, 28),
+    (ArithmeticOverflow, 29),
+    (InvalidAccountData, 30),
+    (InvalidSignature, 31),
+    (AccountNotAssigned, 32),
+    (EmptyInstructionData, 33),
+    (NotEnoughAccounts, 34),
+    (SerializationFailed, 35),
+    (DisplayNameTooLong, 36),
+    (AvatarUriTooLong, 37),
+    (TooManyReactions, 38),
+    (RecipientMismatch, 39),
+    (ParticipantMismatch, 40),
+    (AlreadyInitialized, 41),
+    (DuplicateDraft, 42),
+    (SenderMismatch, 43),
+    (DuplicateMailId, 44),
+  ];
+
+  #[test]
+  fn test_error_codes_are_frozen() {
+    for (error, code) in CODES.iter() {
+      assert_eq!(ProgramError::from(*error), ProgramError::Custom(*code));
+    }
+  }
+
+  #[test]
+  fn test_error_codes_round_trip_and_messages_unique() {
+    let mut codes = BTreeSet::new();
+    let mut messages = BTreeSet::new();
+
+    for (error, _) in CODES.iter() {
+      let code = match ProgramError::from(*error) {
+        ProgramError::Custom(code) => code,
+        other => panic!("{:?} maps to {:?}", error, other),
+      };
+      assert_eq!(code, *error as u32);
+      assert!(codes.insert(code), "{:?} reuses code {}", error, code);
+
+      let message = error.to_string();
+      assert!(!message.is_empty(), "{:?} has no message", error);
+      assert!(messages.insert(message), "{:?} reuses a message", error);
+    }
+  }
+}
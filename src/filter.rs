@@ -0,0 +1,54 @@
+//! Heuristics that score incoming mail. Every validator replays them, so they must stay
+//! deterministic: no clock, randomness or state beyond the mail and the receiving mailbox.
+
+use crate::limits::MAX_PRIORITY;
+use crate::state::Mail;
+
+/// Subject words that mark a mail as time-sensitive, matched ignoring ASCII case.
+const URGENT_KEYWORDS: &[&str] = &["urgent", "asap", "important", "action required"];
+
+/// Priority for a mail sent at normal priority: one level up when the subject carries an
+/// urgent keyword, and one more when the sender is in the receiver's `contacts`. Capped at
+/// `MAX_PRIORITY`.
+pub fn classify(mail: &Mail, contacts: &[(String, String)]) -> u8 {
+  let subject = mail.subject.to_ascii_lowercase();
+  let mut priority = 0;
+
+  if URGENT_KEYWORDS
+    .iter()
+    .any(|keyword| subject.contains(keyword))
+  {
+    priority += 1;
+  }
+
+  if contacts
+    .iter()
+    .any(|(_, address)| *address == mail.from_address)
+  {
+    priority += 1;
+  }
+
+  priority.min(MAX_PRIORITY)
+}
+
+// Sanity tests
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_classify() {
+    let mail = |subject: &str| Mail {
+      from_address: String::from("sender"),
+      subject: String::from(subject),
+      ..Mail::default()
+    };
+    let contacts = vec![(String::from("sam"), String::from("sender"))];
+
+    assert_eq!(classify(&mail("Lunch?"), &[]), 0);
+    assert_eq!(classify(&mail("URGENT: server down"), &[]), 1);
+    assert_eq!(classify(&mail("Action required on your invoice"), &[]), 1);
+    assert_eq!(classify(&mail("Lunch?"), &contacts), 1);
+    assert_eq!(classify(&mail("Reply asap"), &contacts), 2);
+  }
+}
@@ -1,34 +1,712 @@
-use crate::error::MailError::InvalidInstruction;
-use crate::state::Mail;
+use crate::error::MailError::{EmptyInstructionData, InvalidInstruction};
+use crate::state::{Folder, Mail, Profile};
 use borsh::BorshDeserialize;
 use solana_program::program_error::ProgramError;
 
+/// The native program that checks Ed25519 signatures listed in its instruction data.
+pub mod ed25519_program {
+  solana_program::declare_id!("Ed25519SigVerify111111111111111111111111111");
+}
+
 #[derive(Debug, PartialEq)]
 pub enum MailInstruction {
-  /// Initialize a new account
+  /// Create and initialize a new account at the owner's mailbox address, see
+  /// `find_mailbox_address`. The program creates the account itself, `MAILBOX_LEN` bytes
+  /// large and funded by the owner, whose key becomes the mailbox owner.
   ///
   /// Accounts expected
   ///
   /// 1. `[writable]` The AccountInfo of the account to be initialized
-  InitAccount,
-  /// Send a mail to an account.
+  /// 2. `[writable, signer]` The AccountInfo of the owner, paying the rent
+  /// 3. `[]` The System Program
+  ///
+  /// With `send_welcome` set the inbox starts with a welcome mail, using `welcome_subject`
+  /// and `welcome_body` when given and the SolMail greeting otherwise. Instruction data
+  /// holding only the tag initializes an empty mailbox.
+  InitAccount {
+    send_welcome: bool,
+    welcome_subject: Option<String>,
+    welcome_body: Option<String>,
+  },
+  /// Send a mail to an account. With `require_receiver_signature` set the receiver's owner
+  /// must sign, so delivery only succeeds into an inbox that consents to it.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[writable]` The AccountInfo of the receiver
+  /// 3. `[signer]` The owner of the sender's mailbox
+  /// 4. `[signer]` The owner of the receiver's mailbox, only when consent is required
+  /// 5. `[]` The instructions sysvar, only when `verify_signature` is set
+  ///
+  /// Both accounts must already be initialized with `InitAccount`; the receiver is never
+  /// initialized implicitly. The mail's `from_address` must be the sender's address, here and
+  /// in every other instruction that sends mail.
+  ///
+  /// `nonce` must equal the sender's current `send_nonce`, so replaying this `SendMail` is
+  /// rejected. Only `SendMail` checks and advances the nonce; the other sending instructions
+  /// rely on the transaction's recent blockhash alone.
+  ///
+  /// With `verify_signature` set, the transaction must also carry an Ed25519 program
+  /// instruction checking `mail.signature` by the sender's owner over `Mail::signing_message`,
+  /// since the sender mailbox itself has no private key.
+  ///
+  /// With `normalize_subject` set the subject is stored as returned by `normalize_text`. The
+  /// program must be built with the `normalize` feature for this; otherwise the instruction
+  /// is rejected.
+  ///
+  /// The receiver's copy of a mail sent at priority `0` gets the priority assigned by
+  /// `filter::classify`, here and in every other instruction that delivers mail.
+  SendMail {
+    mail: Mail,
+    require_receiver_signature: bool,
+    nonce: u64,
+    verify_signature: bool,
+    normalize_subject: bool,
+  },
+  /// Replace a saved draft, keeping its position in the drafts folder. The new draft is
+  /// checked like a mail being sent.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account holding the draft
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  UpdateDraft { mail_id: String, mail: Mail },
+  /// Remove every read message from the inbox.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account to clean up
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  DeleteAllRead,
+  /// Remove every inbox and sent message whose expiry has passed.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account to clean up
+  PurgeExpired,
+  /// Save an address under an alias in the contacts list.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account owning the contacts
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  AddContact { alias: String, address: String },
+  /// Remove the contact saved under an alias.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account owning the contacts
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  RemoveContact { alias: String },
+  /// Write the mailbox folder counts into a scratch account.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox to summarize
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the stats
+  GetStats,
+  /// Send a mail to several accounts without revealing the recipients to each other.
+  /// Each receiver's copy is addressed to that receiver alone, while the sender's
+  /// copy lists every recipient in `bcc`.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  /// 3. `[writable]` The AccountInfo of each receiver, one to `MAX_RECIPIENTS`
+  ///
+  /// Receivers are delivered to in account order, which is also the order of the sender
+  /// copy's `bcc`.
+  SendMailBcc { mail: Mail },
+  /// Tag an inbox mail with a label. Adding a label the mail already has does nothing.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account holding the mail
+  AddLabel { mail_id: String, label: String },
+  /// Remove a label from an inbox mail.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account holding the mail
+  RemoveLabel { mail_id: String, label: String },
+  /// Take back a mail sent within the undo window, removing it from the receiver's inbox
+  /// and the sender's sent folder.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[writable]` The AccountInfo of the receiver
+  /// 3. `[signer]` The owner of the sender's mailbox
+  UndoSend { mail_id: String },
+  /// Write a single inbox or sent mail into a scratch account.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox holding the mail
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the mail
+  GetMessage { mail_id: String },
+  /// Hand the mailbox over to a new owner.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the current owner
+  TransferMailbox { new_owner: String },
+  /// Send several distinct mails in one go, each to its own receiver. Nothing is
+  /// delivered unless every mail and receiver is valid.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  /// 3. `[writable]` The AccountInfo of the receiver of each mail, in the order of `mails`,
+  ///    matching its `to_address`
+  ///
+  /// At most `MAX_RECIPIENTS` mails can be sent in one batch.
+  ///
+  /// With `best_effort` set, a mail that fails validation or whose receiver is not a
+  /// writable account of this program, is not its `to_address` or cannot take it is
+  /// skipped rather than failing the batch, and a `Vec<(String, bool)>` of each receiver's
+  /// address and whether it got its mail is written into a signing scratch account passed
+  /// after the receivers. Instruction data holding only the mails sends strictly.
+  SendBatch { mails: Vec<Mail>, best_effort: bool },
+  /// Record the position of the last inbox mail read.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetReadCursor { index: u32 },
+  /// Move a mail between folders.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  MoveMail {
+    mail_id: String,
+    from: Folder,
+    to: Folder,
+  },
+  /// Send a mail to every member of a group.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  /// 3. `[]` The AccountInfo of the group
+  /// 4. `[writable]` The AccountInfo of each member's mailbox, in the group's member order
+  ///
+  /// Groups larger than `MAX_RECIPIENTS` cannot be sent to in one instruction.
+  SendToGroup { mail: Mail },
+  /// Fill in the `sent_at` timestamp of mail stored before it existed, parsed from `sent_date`.
+  /// A mailbox still in the first release's layout is upgraded to the current one first.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account to migrate
+  MigrateDates,
+  /// Take back a sent mail the receiver has not read yet, removing it from the receiver's
+  /// inbox and the sender's sent folder. Unlike `UndoSend` there is no time window.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[writable]` The AccountInfo of the receiver
+  /// 3. `[signer]` The owner of the sender's mailbox
+  RecallMail { mail_id: String },
+  /// Mark every inbox message read and move the read cursor to the last one.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  MarkAllRead,
+  /// Move an inbox mail to the trash.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  DeleteMail { mail_id: String },
+  /// Move a mail from the trash back to the inbox.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  RestoreFromTrash { mail_id: String },
+  /// Permanently remove every mail in the trash.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  EmptyTrash,
+  /// Change the subject of a mail in the sender's sent folder. The receiver's copy is not
+  /// touched.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  EditSentSubject {
+    mail_id: String,
+    new_subject: String,
+  },
+  /// Turn contacts-only delivery on or off. While enabled, mail is rejected unless the
+  /// sending mailbox's address is one of the mailbox's contacts.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetAllowlistMode { enabled: bool },
+  /// Run the checks of `SendMail` for `mail`, including whether both mailboxes have room for
+  /// it, without writing anything. Succeeds after logging `VALIDATE_OK`.
   ///
   /// Accounts expected:
   ///
   /// 1. `[writable]` The AccountInfo of the sender
   /// 2. `[writable]` The AccountInfo of the receiver
-  SendMail { mail: Mail },
+  /// 3. `[signer]` The owner of the sender's mailbox
+  ValidateSend { mail: Mail },
+  /// Write up to `limit` inbox mails starting at `offset` into a scratch account, as a `Page`.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the page
+  GetInboxPage { offset: u32, limit: u32 },
+  /// Recompute the length header of a mailbox whose header disagrees with its body.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox to repair
+  RepairHeader,
+  /// Zero the bytes after the stored mailbox, such as those left behind by removed
+  /// messages. This frees no space: every store already writes the mailbox at its packed
+  /// length, and the account keeps its allocated size and rent.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox to scrub
+  ScrubTail,
+  /// Write the `SentStatus` of a mail in the sender's sent folder into a scratch account.
+  /// A mail missing from the receiver's inbox, for example after a recall or delete, is
+  /// reported as not delivered.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the sender
+  /// 2. `[]` The AccountInfo of the receiver
+  /// 3. `[writable, signer]` The AccountInfo of the scratch account receiving the status
+  GetSentStatus { mail_id: String },
+  /// Set how many days read inbox mail is kept before `AutoArchive` moves it, or turn
+  /// auto-archiving off with `None`.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetAutoArchive { days: Option<u32> },
+  /// Move read inbox mail older than the mailbox's `auto_archive_days` to the archive.
+  /// Does nothing while auto-archiving is off.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  AutoArchive,
+  /// Forward a stored mail to `to_address` as a new mail from the forwarding mailbox, with
+  /// `Fwd: ` before its subject. The copy gets the client-chosen `forward_id` and is sent now,
+  /// so it does not share the original's id, date or expiry. With `strip_attachments` set the
+  /// forwarded copy carries no attachment URIs.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the forwarding mailbox
+  /// 2. `[writable]` The AccountInfo of the receiver at `to_address`
+  /// 3. `[signer]` The owner of the forwarding mailbox
+  ForwardMail {
+    mail_id: String,
+    forward_id: String,
+    to_address: String,
+    strip_attachments: bool,
+  },
+  /// Copy every message of the source mailbox into the same folder of the destination,
+  /// skipping ids the destination already holds. With `close_source` set the source's data
+  /// is cleared and its lamports move to the destination.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the source mailbox
+  /// 2. `[writable]` The AccountInfo of the destination mailbox
+  /// 3. `[signer]` The AccountInfo of the owner of both mailboxes
+  MergeMailbox { close_source: bool },
+  /// Set the mailbox's `max_age_secs`, or keep inbox mail indefinitely with `None`. Every
+  /// delivery into the mailbox first drops inbox mail older than that.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetRetention { max_age_secs: Option<u64> },
+  /// Mark an inbox mail as `acknowledged`, an explicit receipt signed by the receiver's
+  /// owner. When the sender's mailbox is passed, its sent copy is marked too.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the receiver
+  /// 2. `[signer]` The owner of the receiver's mailbox
+  /// 3. `[writable]` Optional, the AccountInfo of the sender
+  AcknowledgeMail { mail_id: String },
+  /// Remove every inbox message in the conversation started by `root_id`, following
+  /// `in_reply_to` links, and the sent ones too with `include_sent` set.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  DeleteThread { root_id: String, include_sent: bool },
+  /// Write the whole mailbox into a scratch account, in the mailbox layout, so it can be
+  /// backed up with a single account read.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the export
+  ExportMailbox,
+  /// Restore a mailbox from a blob written by `ExportMailbox`. With `merge` set the backup's
+  /// messages are added as by `MergeMailbox`; otherwise the backup replaces the mailbox,
+  /// keeping its owner and send nonce.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the scratch account holding the backup
+  /// 2. `[writable]` The AccountInfo of the mailbox to restore into
+  /// 3. `[signer]` The AccountInfo of the mailbox owner
+  ImportMailbox { merge: bool },
+  /// Queue a mail in the sender's mailbox to be delivered by `ProcessScheduled` once
+  /// `deliver_at` has passed. Nothing is delivered yet, but `to_address` must already be a
+  /// pubkey.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  ScheduleMail { mail: Mail, deliver_at: u64 },
+  /// Deliver up to `MAX_RECIPIENTS` scheduled mails that are due and drop them from the
+  /// queue. Mail that is not yet due, or beyond that limit, stays queued, as does a due mail
+  /// whose receiver account does not match its `to_address` or cannot take it.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[writable]` The AccountInfo of the receiver of each due mail handled, in queue order
+  ProcessScheduled,
+  /// Turn the sender-side copy of sent mail on or off.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetKeepSent { enabled: bool },
+  /// Write the number of unread inbox mails into a scratch account as a `u32`, or log it as
+  /// `UNREAD <count>` when no scratch account is passed.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` Optional, the scratch account receiving the count
+  GetUnreadCount,
+  /// Write up to `count` mails of `folder` starting at `start` into a scratch account, as a
+  /// `Page`. A start past the end of the folder writes an empty page.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the page
+  GetFolder {
+    folder: Folder,
+    start: u32,
+    count: u32,
+  },
+  /// Set the mailbox's profile, or clear it with `None`.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SetProfile { profile: Option<Profile> },
+  /// Write the mailbox's `total_bytes`, the packed size of its folders, into a scratch
+  /// account as a `u32`.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the size
+  GetStorageUsage,
+  /// React to a mail in any folder of the mailbox with `emoji`, as the signing account. A
+  /// second reaction from the same account replaces the first.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the mailbox holding the mail
+  /// 2. `[signer]` The AccountInfo of the reacting account
+  AddReaction { mail_id: String, emoji: String },
+  /// Write the `(folder, id)` of every mail whose subject or body contains `query`, ignoring
+  /// case, into a scratch account. At most `MAX_SEARCH_RESULTS` matches are written.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the matches
+  SearchMailbox { query: String },
+  /// Write the mail at position `index` of `folder` into a scratch account.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[]` The AccountInfo of the mailbox
+  /// 2. `[writable, signer]` The AccountInfo of the scratch account receiving the mail
+  GetMessageByIndex { folder: Folder, index: u32 },
+  /// Reply to the mail `mail.in_reply_to` names in the replier's mailbox, delivering to everyone
+  /// on it but the replier.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the replier
+  /// 2. `[signer]` The owner of the replier's mailbox
+  /// 3. `[writable]` The AccountInfo of each participant of the original: its `from_address`,
+  ///    then `to_address`, then `bcc`, without repeats and leaving out the replier
+  ReplyAll { mail: Mail },
+  /// Replace the members of a group. The first call on an account never written to claims the
+  /// group for the owner signer, and the group account must sign it too; later calls must be
+  /// signed by that owner.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the group, `[signer]` on the first call
+  /// 2. `[signer]` The AccountInfo of the group's owner
+  ///
+  /// Every member must be a pubkey, and at most `MAX_RECIPIENTS` of them can be set.
+  SetGroupMembers { members: Vec<String> },
+  /// Append a new draft to the drafts folder, checked like a mail being sent. Its id must not
+  /// already be taken by a draft.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the account holding the draft
+  /// 2. `[signer]` The AccountInfo of the mailbox owner
+  SaveDraft { mail: Mail },
+  /// Drop a mail from the sender's scheduled queue before it is delivered.
+  ///
+  /// Accounts expected:
+  ///
+  /// 1. `[writable]` The AccountInfo of the sender
+  /// 2. `[signer]` The owner of the sender's mailbox
+  CancelScheduled { mail_id: String },
+}
+
+/// Deserialize an instruction payload, rejecting one with bytes left over.
+fn unpack_payload<T: BorshDeserialize>(rest: &[u8]) -> Result<T, ProgramError> {
+  T::try_from_slice(rest).map_err(|_| InvalidInstruction.into())
 }
 
 impl MailInstruction {
-  /// Deserialize byte buffer into a [MailInstruction](enum.MailInstruction.html).
+  /// Deserialize byte buffer into a [MailInstruction](enum.MailInstruction.html). A payload
+  /// that does not decode exactly, including one followed by extra bytes, is rejected with
+  /// `InvalidInstruction`, as is an unknown tag. Empty input fails with `EmptyInstructionData`.
   pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
+    let (tag, rest) = input.split_first().ok_or(EmptyInstructionData)?;
 
     Ok(match tag {
-      0 => Self::InitAccount,
-      1 => Self::SendMail {
-        mail: Mail::try_from_slice(&rest)?,
+      0 if rest.is_empty() => Self::InitAccount {
+        send_welcome: false,
+        welcome_subject: None,
+        welcome_body: None,
+      },
+      0 => {
+        let (send_welcome, welcome_subject, welcome_body) =
+          unpack_payload::<(bool, Option<String>, Option<String>)>(rest)?;
+        Self::InitAccount {
+          send_welcome,
+          welcome_subject,
+          welcome_body,
+        }
+      }
+      1 => {
+        let (mail, require_receiver_signature, nonce, verify_signature, normalize_subject) =
+          unpack_payload::<(Mail, bool, u64, bool, bool)>(rest)?;
+        Self::SendMail {
+          mail,
+          require_receiver_signature,
+          nonce,
+          verify_signature,
+          normalize_subject,
+        }
+      }
+      2 => {
+        let (mail_id, mail) = unpack_payload::<(String, Mail)>(rest)?;
+        Self::UpdateDraft { mail_id, mail }
+      }
+      3 if rest.is_empty() => Self::DeleteAllRead,
+      4 if rest.is_empty() => Self::PurgeExpired,
+      5 => {
+        let (alias, address) = unpack_payload::<(String, String)>(rest)?;
+        Self::AddContact { alias, address }
+      }
+      6 => Self::RemoveContact {
+        alias: unpack_payload::<String>(rest)?,
+      },
+      7 if rest.is_empty() => Self::GetStats,
+      8 => Self::SendMailBcc {
+        mail: unpack_payload::<Mail>(rest)?,
+      },
+      9 => {
+        let (mail_id, label) = unpack_payload::<(String, String)>(rest)?;
+        Self::AddLabel { mail_id, label }
+      }
+      10 => {
+        let (mail_id, label) = unpack_payload::<(String, String)>(rest)?;
+        Self::RemoveLabel { mail_id, label }
+      }
+      11 => Self::UndoSend {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      12 => Self::GetMessage {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      13 => Self::TransferMailbox {
+        new_owner: unpack_payload::<String>(rest)?,
+      },
+      14 => {
+        let (mails, best_effort) = unpack_payload::<(Vec<Mail>, bool)>(rest)
+          .or_else(|_| unpack_payload::<Vec<Mail>>(rest).map(|mails| (mails, false)))?;
+        Self::SendBatch { mails, best_effort }
+      }
+      15 => Self::SetReadCursor {
+        index: unpack_payload::<u32>(rest)?,
+      },
+      16 => {
+        let (mail_id, from, to) = unpack_payload::<(String, Folder, Folder)>(rest)?;
+        Self::MoveMail { mail_id, from, to }
+      }
+      17 => Self::SendToGroup {
+        mail: unpack_payload::<Mail>(rest)?,
+      },
+      18 if rest.is_empty() => Self::MigrateDates,
+      19 => Self::RecallMail {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      20 if rest.is_empty() => Self::MarkAllRead,
+      21 => Self::DeleteMail {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      22 => Self::RestoreFromTrash {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      23 if rest.is_empty() => Self::EmptyTrash,
+      24 => {
+        let (mail_id, new_subject) = unpack_payload::<(String, String)>(rest)?;
+        Self::EditSentSubject {
+          mail_id,
+          new_subject,
+        }
+      }
+      25 => Self::SetAllowlistMode {
+        enabled: unpack_payload::<bool>(rest)?,
+      },
+      26 => Self::ValidateSend {
+        mail: unpack_payload::<Mail>(rest)?,
+      },
+      27 => {
+        let (offset, limit) = unpack_payload::<(u32, u32)>(rest)?;
+        Self::GetInboxPage { offset, limit }
+      }
+      28 if rest.is_empty() => Self::RepairHeader,
+      29 if rest.is_empty() => Self::ScrubTail,
+      30 => Self::GetSentStatus {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      31 => Self::SetAutoArchive {
+        days: unpack_payload::<Option<u32>>(rest)?,
+      },
+      32 if rest.is_empty() => Self::AutoArchive,
+      33 => {
+        let (mail_id, forward_id, to_address, strip_attachments) =
+          unpack_payload::<(String, String, String, bool)>(rest)?;
+        Self::ForwardMail {
+          mail_id,
+          forward_id,
+          to_address,
+          strip_attachments,
+        }
+      }
+      34 => Self::MergeMailbox {
+        close_source: unpack_payload::<bool>(rest)?,
+      },
+      35 => Self::SetRetention {
+        max_age_secs: unpack_payload::<Option<u64>>(rest)?,
+      },
+      36 => Self::AcknowledgeMail {
+        mail_id: unpack_payload::<String>(rest)?,
+      },
+      37 => {
+        let (root_id, include_sent) = unpack_payload::<(String, bool)>(rest)?;
+        Self::DeleteThread {
+          root_id,
+          include_sent,
+        }
+      }
+      38 if rest.is_empty() => Self::ExportMailbox,
+      39 => Self::ImportMailbox {
+        merge: unpack_payload::<bool>(rest)?,
+      },
+      40 => {
+        let (mail, deliver_at) = unpack_payload::<(Mail, u64)>(rest)?;
+        Self::ScheduleMail { mail, deliver_at }
+      }
+      41 if rest.is_empty() => Self::ProcessScheduled,
+      42 => Self::SetKeepSent {
+        enabled: unpack_payload::<bool>(rest)?,
+      },
+      43 if rest.is_empty() => Self::GetUnreadCount,
+      44 => {
+        let (folder, start, count) = unpack_payload::<(Folder, u32, u32)>(rest)?;
+        Self::GetFolder {
+          folder,
+          start,
+          count,
+        }
+      }
+      45 => Self::SetProfile {
+        profile: unpack_payload::<Option<Profile>>(rest)?,
+      },
+      46 if rest.is_empty() => Self::GetStorageUsage,
+      47 => {
+        let (mail_id, emoji) = unpack_payload::<(String, String)>(rest)?;
+        Self::AddReaction { mail_id, emoji }
+      }
+      48 => Self::SearchMailbox {
+        query: unpack_payload::<String>(rest)?,
+      },
+      49 => {
+        let (folder, index) = unpack_payload::<(Folder, u32)>(rest)?;
+        Self::GetMessageByIndex { folder, index }
+      }
+      50 => Self::ReplyAll {
+        mail: unpack_payload::<Mail>(rest)?,
+      },
+      51 => Self::SetGroupMembers {
+        members: unpack_payload::<Vec<String>>(rest)?,
+      },
+      52 => Self::SaveDraft {
+        mail: unpack_payload::<Mail>(rest)?,
+      },
+      53 => Self::CancelScheduled {
+        mail_id: unpack_payload::<String>(rest)?,
       },
       _ => return Err(InvalidInstruction.into()),
     })
@@ -48,41 +726,801 @@ mod test {
 
     let mail_instruction = MailInstruction::unpack(&data).unwrap();
 
-    assert_eq!(mail_instruction, MailInstruction::InitAccount);
+    assert_eq!(
+      mail_instruction,
+      MailInstruction::InitAccount {
+        send_welcome: false,
+        welcome_subject: None,
+        welcome_body: None,
+      }
+    );
+
+    let mut data: Vec<u8> = vec![0];
+    data.extend(
+      (true, Some(String::from("Hi")), None::<String>)
+        .try_to_vec()
+        .unwrap(),
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::InitAccount {
+        send_welcome: true,
+        welcome_subject: Some(String::from("Hi")),
+        welcome_body: None,
+      }
+    );
   }
 
   #[test]
-  fn test_send_endpoint() {
-    let test_mail = Mail {
-      id: String::from("00000000-0000-0000-0000-000000000000"),
-      from_address: Pubkey::default().to_string(),
-      to_address: Pubkey::default().to_string(),
-      subject: String::from("Hey Mike"),
-      body: String::from("Body text with some characters"),
-      sent_date: String::from("9/29/2021, 3:58:02 PM"),
-    };
+  fn test_delete_all_read_endpoint() {
+    let data: Vec<u8> = vec![3];
 
-    let mut data: Vec<u8> = vec![1; get_instance_packed_len(&test_mail).unwrap() + 1];
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::DeleteAllRead);
+  }
 
-    test_mail.serialize(&mut &mut data[1..]).unwrap();
+  #[test]
+  fn test_purge_expired_endpoint() {
+    let data: Vec<u8> = vec![4];
 
     let mail_instruction = MailInstruction::unpack(&data).unwrap();
 
-    assert_eq!(
-      mail_instruction,
-      MailInstruction::SendMail {
-        mail: test_mail.clone()
-      }
-    );
+    assert_eq!(mail_instruction, MailInstruction::PurgeExpired);
+  }
 
-    match mail_instruction {
-      MailInstruction::SendMail { mail } => {
-        assert_eq!(mail.from_address, test_mail.from_address);
-        assert_eq!(mail.to_address, test_mail.to_address);
-        assert_eq!(mail.subject, test_mail.subject);
-        assert_eq!(mail.body, test_mail.body);
-      }
-      MailInstruction::InitAccount => (),
-    }
+  #[test]
+  fn test_migrate_dates_endpoint() {
+    let data: Vec<u8> = vec![18];
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::MigrateDates);
+  }
+
+  #[test]
+  fn test_mark_all_read_endpoint() {
+    let data: Vec<u8> = vec![20];
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::MarkAllRead);
+  }
+
+  #[test]
+  fn test_trash_endpoints() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+
+    let mut data: Vec<u8> = vec![21];
+    data.extend(mail_id.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::DeleteMail {
+        mail_id: mail_id.clone()
+      }
+    );
+
+    data[0] = 22;
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::RestoreFromTrash { mail_id }
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&[23]).unwrap(),
+      MailInstruction::EmptyTrash
+    );
+  }
+
+  #[test]
+  fn test_repair_header_endpoint() {
+    let data: Vec<u8> = vec![28];
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::RepairHeader);
+  }
+
+  #[test]
+  fn test_get_sent_status_endpoint() {
+    let mut data: Vec<u8> = vec![30];
+    data.extend(String::from("1").try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::GetSentStatus {
+        mail_id: String::from("1")
+      }
+    );
+  }
+
+  #[test]
+  fn test_auto_archive_endpoints() {
+    let mut data: Vec<u8> = vec![31];
+    data.extend(Some(7u32).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetAutoArchive { days: Some(7) }
+    );
+
+    let data: Vec<u8> = vec![32];
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::AutoArchive
+    );
+  }
+
+  #[test]
+  fn test_forward_mail_endpoint() {
+    let mut data: Vec<u8> = vec![33];
+    data.extend(
+      (
+        String::from("1"),
+        String::from("2"),
+        Pubkey::default().to_string(),
+        true,
+      )
+        .try_to_vec()
+        .unwrap(),
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::ForwardMail {
+        mail_id: String::from("1"),
+        forward_id: String::from("2"),
+        to_address: Pubkey::default().to_string(),
+        strip_attachments: true,
+      }
+    );
+  }
+
+  #[test]
+  fn test_merge_mailbox_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[34, 1]).unwrap(),
+      MailInstruction::MergeMailbox { close_source: true }
+    );
+  }
+
+  #[test]
+  fn test_set_retention_endpoint() {
+    let mut data: Vec<u8> = vec![35];
+    data.extend(Some(3_600u64).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetRetention {
+        max_age_secs: Some(3_600)
+      }
+    );
+  }
+
+  #[test]
+  fn test_acknowledge_mail_endpoint() {
+    let mut data: Vec<u8> = vec![36];
+    data.extend(String::from("1").try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::AcknowledgeMail {
+        mail_id: String::from("1")
+      }
+    );
+  }
+
+  #[test]
+  fn test_delete_thread_endpoint() {
+    let mut data: Vec<u8> = vec![37];
+    data.extend((String::from("1"), true).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::DeleteThread {
+        root_id: String::from("1"),
+        include_sent: true,
+      }
+    );
+  }
+
+  #[test]
+  fn test_export_mailbox_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[38]).unwrap(),
+      MailInstruction::ExportMailbox
+    );
+  }
+
+  #[test]
+  fn test_import_mailbox_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[39, 0]).unwrap(),
+      MailInstruction::ImportMailbox { merge: false }
+    );
+  }
+
+  #[test]
+  fn test_schedule_mail_endpoints() {
+    let mail = Mail {
+      id: String::from("1"),
+      subject: String::from("Later"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![40];
+    data.extend((mail.clone(), 1_700_000_000u64).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::ScheduleMail {
+        mail,
+        deliver_at: 1_700_000_000
+      }
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&[41]).unwrap(),
+      MailInstruction::ProcessScheduled
+    );
+
+    let mut data: Vec<u8> = vec![53];
+    data.extend(String::from("1").try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::CancelScheduled {
+        mail_id: String::from("1")
+      }
+    );
+  }
+
+  #[test]
+  fn test_set_keep_sent_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[42, 0]).unwrap(),
+      MailInstruction::SetKeepSent { enabled: false }
+    );
+  }
+
+  #[test]
+  fn test_get_unread_count_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[43]).unwrap(),
+      MailInstruction::GetUnreadCount
+    );
+  }
+
+  #[test]
+  fn test_get_folder_endpoint() {
+    let mut data: Vec<u8> = vec![44];
+    data.extend((Folder::Archive, 10u32, 5u32).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::GetFolder {
+        folder: Folder::Archive,
+        start: 10,
+        count: 5
+      }
+    );
+
+    data[1] = 5;
+    assert_eq!(
+      MailInstruction::unpack(&data),
+      Err(InvalidInstruction.into())
+    );
+  }
+
+  #[test]
+  fn test_set_profile_endpoint() {
+    let profile = Profile {
+      display_name: String::from("Mike"),
+      avatar_uri: String::from("ipfs://avatar"),
+    };
+
+    let mut data: Vec<u8> = vec![45];
+    data.extend(Some(profile.clone()).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetProfile {
+        profile: Some(profile)
+      }
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&[45, 0]).unwrap(),
+      MailInstruction::SetProfile { profile: None }
+    );
+  }
+
+  #[test]
+  fn test_get_storage_usage_endpoint() {
+    assert_eq!(
+      MailInstruction::unpack(&[46]).unwrap(),
+      MailInstruction::GetStorageUsage
+    );
+  }
+
+  #[test]
+  fn test_add_reaction_endpoint() {
+    let mut data: Vec<u8> = vec![47];
+    data.extend(
+      (String::from("1"), String::from("👍"))
+        .try_to_vec()
+        .unwrap(),
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::AddReaction {
+        mail_id: String::from("1"),
+        emoji: String::from("👍")
+      }
+    );
+  }
+
+  #[test]
+  fn test_search_mailbox_endpoint() {
+    let mut data: Vec<u8> = vec![48];
+    data.extend(String::from("invoice").try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SearchMailbox {
+        query: String::from("invoice")
+      }
+    );
+  }
+
+  #[test]
+  fn test_get_message_by_index_endpoint() {
+    let mut data: Vec<u8> = vec![49];
+    data.extend((Folder::Sent, 3u32).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::GetMessageByIndex {
+        folder: Folder::Sent,
+        index: 3
+      }
+    );
+  }
+
+  #[test]
+  fn test_scrub_tail_endpoint() {
+    let data: Vec<u8> = vec![29];
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::ScrubTail);
+  }
+
+  #[test]
+  fn test_contact_endpoints() {
+    let alias = String::from("mike");
+    let address = Pubkey::default().to_string();
+
+    let mut data: Vec<u8> = vec![5];
+    data.extend((alias.clone(), address.clone()).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::AddContact {
+        alias: alias.clone(),
+        address
+      }
+    );
+
+    let mut data: Vec<u8> = vec![6];
+    data.extend(alias.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::RemoveContact { alias }
+    );
+  }
+
+  #[test]
+  fn test_label_endpoints() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+    let label = String::from("work");
+
+    let mut data: Vec<u8> = vec![9];
+    data.extend((mail_id.clone(), label.clone()).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::AddLabel {
+        mail_id: mail_id.clone(),
+        label: label.clone()
+      }
+    );
+
+    data[0] = 10;
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::RemoveLabel { mail_id, label }
+    );
+  }
+
+  #[test]
+  fn test_undo_send_endpoint() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+
+    let mut data: Vec<u8> = vec![11];
+    data.extend(mail_id.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::UndoSend { mail_id }
+    );
+  }
+
+  #[test]
+  fn test_recall_mail_endpoint() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+
+    let mut data: Vec<u8> = vec![19];
+    data.extend(mail_id.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::RecallMail { mail_id }
+    );
+  }
+
+  #[test]
+  fn test_get_message_endpoint() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+
+    let mut data: Vec<u8> = vec![12];
+    data.extend(mail_id.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::GetMessage { mail_id }
+    );
+  }
+
+  #[test]
+  fn test_transfer_mailbox_endpoint() {
+    let new_owner = Pubkey::default().to_string();
+
+    let mut data: Vec<u8> = vec![13];
+    data.extend(new_owner.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::TransferMailbox { new_owner }
+    );
+  }
+
+  #[test]
+  fn test_set_read_cursor_endpoint() {
+    let mut data: Vec<u8> = vec![15];
+    data.extend(7u32.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetReadCursor { index: 7 }
+    );
+  }
+
+  #[test]
+  fn test_set_allowlist_mode_endpoint() {
+    let mut data: Vec<u8> = vec![25];
+    data.extend(true.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetAllowlistMode { enabled: true }
+    );
+  }
+
+  #[test]
+  fn test_get_inbox_page_endpoint() {
+    let mut data: Vec<u8> = vec![27];
+    data.extend((10u32, 5u32).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::GetInboxPage {
+        offset: 10,
+        limit: 5
+      }
+    );
+  }
+
+  #[test]
+  fn test_move_mail_endpoint() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+
+    let mut data: Vec<u8> = vec![16];
+    data.extend(
+      (mail_id.clone(), Folder::Inbox, Folder::Archive)
+        .try_to_vec()
+        .unwrap(),
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::MoveMail {
+        mail_id,
+        from: Folder::Inbox,
+        to: Folder::Archive
+      }
+    );
+
+    let last = data.len() - 1;
+    data[last] = 9;
+    assert!(MailInstruction::unpack(&data).is_err());
+  }
+
+  #[test]
+  fn test_get_stats_endpoint() {
+    let data: Vec<u8> = vec![7];
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(mail_instruction, MailInstruction::GetStats);
+  }
+
+  #[test]
+  fn test_send_endpoint() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: Pubkey::default().to_string(),
+      to_address: Pubkey::default().to_string(),
+      subject: String::from("Hey Mike"),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![1; get_instance_packed_len(&test_mail).unwrap() + 12];
+
+    (test_mail.clone(), true, 7u64, true, true)
+      .serialize(&mut &mut data[1..])
+      .unwrap();
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(
+      mail_instruction,
+      MailInstruction::SendMail {
+        mail: test_mail.clone(),
+        require_receiver_signature: true,
+        nonce: 7,
+        verify_signature: true,
+        normalize_subject: true,
+      }
+    );
+
+    if let MailInstruction::SendMail { mail, .. } = mail_instruction {
+      assert_eq!(mail.from_address, test_mail.from_address);
+      assert_eq!(mail.to_address, test_mail.to_address);
+      assert_eq!(mail.subject, test_mail.subject);
+      assert_eq!(mail.body, test_mail.body);
+    }
+  }
+
+  #[test]
+  fn test_empty_and_unknown_instruction() {
+    assert_eq!(
+      MailInstruction::unpack(&[]),
+      Err(EmptyInstructionData.into())
+    );
+    assert_eq!(
+      MailInstruction::unpack(&[255]),
+      Err(InvalidInstruction.into())
+    );
+  }
+
+  #[test]
+  fn test_trailing_bytes_rejected() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![1];
+    data.extend((test_mail, false, 0u64, false, false).try_to_vec().unwrap());
+    assert!(MailInstruction::unpack(&data).is_ok());
+
+    data.extend_from_slice(&[0xde, 0xad]);
+    assert_eq!(
+      MailInstruction::unpack(&data),
+      Err(InvalidInstruction.into())
+    );
+
+    assert_eq!(
+      MailInstruction::unpack(&[3, 0]),
+      Err(InvalidInstruction.into())
+    );
+  }
+
+  #[test]
+  fn test_validate_send_endpoint() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![26];
+    data.extend(test_mail.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::ValidateSend { mail: test_mail }
+    );
+  }
+
+  #[test]
+  fn test_send_bcc_endpoint() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: Pubkey::default().to_string(),
+      subject: String::from("Hey everyone"),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![8];
+    data.extend(test_mail.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SendMailBcc { mail: test_mail }
+    );
+  }
+
+  #[test]
+  fn test_send_batch_endpoint() {
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+    let mails = vec![mail("1"), mail("2")];
+
+    let mut data: Vec<u8> = vec![14];
+    data.extend(mails.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SendBatch {
+        mails: mails.clone(),
+        best_effort: false
+      }
+    );
+
+    let mut data: Vec<u8> = vec![14];
+    data.extend((mails.clone(), true).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SendBatch {
+        mails,
+        best_effort: true
+      }
+    );
+  }
+
+  #[test]
+  fn test_send_to_group_endpoint() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      subject: String::from("Hey everyone"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![17];
+    data.extend(test_mail.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SendToGroup { mail: test_mail }
+    );
+  }
+
+  #[test]
+  fn test_edit_sent_subject_endpoint() {
+    let mail_id = String::from("00000000-0000-0000-0000-000000000000");
+    let new_subject = String::from("Hey Mike, fixed");
+
+    let mut data: Vec<u8> = vec![24];
+    data.extend((mail_id.clone(), new_subject.clone()).try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::EditSentSubject {
+        mail_id,
+        new_subject
+      }
+    );
+  }
+
+  #[test]
+  fn test_update_draft_endpoint() {
+    let test_mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: Pubkey::default().to_string(),
+      to_address: Pubkey::default().to_string(),
+      subject: String::from("Hey Mike"),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut data: Vec<u8> = vec![2];
+    data.extend(
+      (test_mail.id.clone(), test_mail.clone())
+        .try_to_vec()
+        .unwrap(),
+    );
+
+    let mail_instruction = MailInstruction::unpack(&data).unwrap();
+
+    assert_eq!(
+      mail_instruction,
+      MailInstruction::UpdateDraft {
+        mail_id: test_mail.id.clone(),
+        mail: test_mail
+      }
+    );
+  }
+
+  #[test]
+  fn test_reply_all_endpoint() {
+    let mail = Mail {
+      id: String::from("2"),
+      in_reply_to: Some(String::from("1")),
+      ..Mail::default()
+    };
+    let mut data: Vec<u8> = vec![50];
+    data.extend(mail.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::ReplyAll { mail }
+    );
+  }
+
+  #[test]
+  fn test_set_group_members_endpoint() {
+    let members = vec![Pubkey::new_unique().to_string()];
+    let mut data: Vec<u8> = vec![51];
+    data.extend(members.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SetGroupMembers { members }
+    );
+  }
+
+  #[test]
+  fn test_save_draft_endpoint() {
+    let mail = Mail {
+      id: String::from("1"),
+      subject: String::from("Draft"),
+      ..Mail::default()
+    };
+    let mut data: Vec<u8> = vec![52];
+    data.extend(mail.try_to_vec().unwrap());
+
+    assert_eq!(
+      MailInstruction::unpack(&data).unwrap(),
+      MailInstruction::SaveDraft { mail }
+    );
   }
 }
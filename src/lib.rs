@@ -1,5 +1,9 @@
+#[cfg(feature = "client")]
+pub mod client;
 pub mod entrypoint;
 pub mod error;
+pub mod filter;
 pub mod instruction;
+pub mod limits;
 pub mod processor;
 pub mod state;
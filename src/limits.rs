@@ -0,0 +1,46 @@
+//! Capacity and length limits the program enforces, public so clients can check a mail or
+//! request before sending it and stay in step with the program.
+
+/// Highest priority a sender may assign to a mail; 0 is normal.
+pub const MAX_PRIORITY: u8 = 3;
+
+/// Longest subject, in bytes.
+pub const MAX_SUBJECT_LEN: usize = 256;
+
+/// Most labels a single mail can carry.
+pub const MAX_LABELS: usize = 16;
+
+/// Most attachment URIs a single mail can reference.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+/// Longest attachment URI, in bytes.
+pub const MAX_ATTACHMENT_URI_LEN: usize = 256;
+
+/// Longest profile display name, in bytes.
+pub const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+/// Longest profile avatar URI, in bytes.
+pub const MAX_AVATAR_URI_LEN: usize = 256;
+
+/// Most receiver accounts a single fan-out send may deliver to.
+pub const MAX_RECIPIENTS: usize = 16;
+
+/// Size of the mailbox account `InitAccount` creates, in bytes: the most an account created
+/// from inside a program can hold.
+pub const MAILBOX_LEN: usize = 10 * 1024;
+
+/// Soft cap on the packed size of a mailbox, in bytes. It sits below `MAILBOX_LEN` so a
+/// mailbox reaches its quota before its account runs out of room.
+pub const MAX_ACCOUNT_BYTES: u32 = 8 * 1024;
+
+/// Most reactions a single mail can carry, one per reacting address.
+pub const MAX_REACTIONS: usize = 16;
+
+/// Most `(folder, id)` matches a single `SearchMailbox` returns.
+pub const MAX_SEARCH_RESULTS: usize = 32;
+
+/// Most idempotency keys a mailbox remembers; older ones are forgotten first.
+pub const MAX_RECENT_KEYS: usize = 32;
+
+/// Seconds after sending during which a mail can still be recalled with `UndoSend`.
+pub const UNDO_WINDOW_SECS: u64 = 30;
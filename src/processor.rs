@@ -1,12 +1,45 @@
-use crate::error::MailError::NotWritable;
-use crate::instruction::MailInstruction;
-use crate::state::{DataLength, Mail, MailAccount};
+use crate::error::MailError::{
+  self, AccountMailCountMismatch, AccountNotAssigned, AccountNotInitialized, AccountTooSmall,
+  AlreadyInitialized, AlreadyRead, ArithmeticOverflow, ContactNotFound, DuplicateContact,
+  DuplicateDraft, GroupMemberMismatch, IndexOutOfBounds, InvalidAccountData, InvalidAddress,
+  InvalidMailboxAddress, InvalidNonce, InvalidSignature, MailIdMismatch, MailNotFound,
+  NotEnoughAccounts, NotWritable, ParticipantMismatch, QuotaExceeded, ReceiverConsentRequired,
+  ReceiverNotInitialized, RecipientMismatch, SenderMismatch, SenderNotAllowlisted,
+  SerializationFailed, TooManyLabels, TooManyRecipients, Unauthorized, UndoWindowExpired,
+};
+use crate::filter::classify;
+use crate::instruction::{ed25519_program, MailInstruction};
+use crate::limits::{MAILBOX_LEN, MAX_ACCOUNT_BYTES, MAX_LABELS, MAX_RECIPIENTS, UNDO_WINDOW_SECS};
+#[cfg(feature = "normalize")]
+use crate::state::normalize_text;
+use crate::state::{
+  find_mailbox_address, Folder, GroupAccount, LegacyMailAccount, Mail, MailAccount, MailboxHeader,
+  Profile, SentStatus, MAILBOX_SEED,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
-  account_info::AccountInfo, borsh::get_instance_packed_len, entrypoint::ProgramResult, msg,
-  program_error::ProgramError, pubkey::Pubkey,
+  account_info::AccountInfo,
+  borsh::get_instance_packed_len,
+  clock::Clock,
+  entrypoint::ProgramResult,
+  msg,
+  program::{invoke, invoke_signed},
+  program_error::ProgramError,
+  pubkey::Pubkey,
+  rent::Rent,
+  system_instruction, system_program,
+  sysvar::{instructions, Sysvar},
 };
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Ed25519 program instruction data starts with a signature count and a padding byte,
+/// followed by one offsets entry of seven `u16` values per signature.
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_LEN: usize = 14;
+const ED25519_SIGNATURE_LEN: usize = 64;
 
 pub struct Processor;
 impl Processor {
@@ -18,219 +51,10148 @@ impl Processor {
     let instruction = MailInstruction::unpack(instruction_data)?;
 
     match instruction {
-      MailInstruction::InitAccount => {
+      MailInstruction::InitAccount {
+        send_welcome,
+        welcome_subject,
+        welcome_body,
+      } => {
         msg!("Instruction: InitAccount");
-        Self::process_init_account(&accounts[0], program_id)
+        let welcome = if send_welcome {
+          Some((welcome_subject, welcome_body))
+        } else {
+          None
+        };
+        Self::process_init_account(accounts, welcome, program_id)
       }
-      MailInstruction::SendMail { mail } => {
+      MailInstruction::SendMail {
+        mail,
+        require_receiver_signature,
+        nonce,
+        verify_signature,
+        normalize_subject,
+      } => {
         msg!("Instruction: SendMail");
-        Self::process_send_mail(accounts, &mail, program_id)
+        Self::process_send_mail(
+          accounts,
+          &mail,
+          require_receiver_signature,
+          nonce,
+          verify_signature,
+          normalize_subject,
+          program_id,
+        )
+      }
+      MailInstruction::UpdateDraft { mail_id, mail } => {
+        msg!("Instruction: UpdateDraft");
+        Self::process_update_draft(accounts, &mail_id, &mail, program_id)
+      }
+      MailInstruction::DeleteAllRead => {
+        msg!("Instruction: DeleteAllRead");
+        Self::process_delete_all_read(accounts, program_id)
+      }
+      MailInstruction::PurgeExpired => {
+        msg!("Instruction: PurgeExpired");
+        Self::process_purge_expired(Self::account_at(accounts, 0)?, program_id)
+      }
+      MailInstruction::AddContact { alias, address } => {
+        msg!("Instruction: AddContact");
+        Self::process_add_contact(accounts, alias, address, program_id)
+      }
+      MailInstruction::RemoveContact { alias } => {
+        msg!("Instruction: RemoveContact");
+        Self::process_remove_contact(accounts, &alias, program_id)
+      }
+      MailInstruction::GetStats => {
+        msg!("Instruction: GetStats");
+        Self::process_get_stats(accounts, program_id)
+      }
+      MailInstruction::SendMailBcc { mail } => {
+        msg!("Instruction: SendMailBcc");
+        Self::process_send_mail_bcc(accounts, &mail, program_id)
+      }
+      MailInstruction::AddLabel { mail_id, label } => {
+        msg!("Instruction: AddLabel");
+        Self::process_add_label(Self::account_at(accounts, 0)?, &mail_id, label, program_id)
+      }
+      MailInstruction::RemoveLabel { mail_id, label } => {
+        msg!("Instruction: RemoveLabel");
+        Self::process_remove_label(Self::account_at(accounts, 0)?, &mail_id, &label, program_id)
+      }
+      MailInstruction::UndoSend { mail_id } => {
+        msg!("Instruction: UndoSend");
+        Self::process_undo_send(accounts, &mail_id, program_id)
+      }
+      MailInstruction::GetMessage { mail_id } => {
+        msg!("Instruction: GetMessage");
+        Self::process_get_message(accounts, &mail_id, program_id)
+      }
+      MailInstruction::TransferMailbox { new_owner } => {
+        msg!("Instruction: TransferMailbox");
+        Self::process_transfer_mailbox(accounts, new_owner, program_id)
+      }
+      MailInstruction::SendBatch { mails, best_effort } => {
+        msg!("Instruction: SendBatch");
+        if best_effort {
+          Self::process_send_batch_best_effort(accounts, &mails, program_id)
+        } else {
+          Self::process_send_batch(accounts, &mails, program_id)
+        }
+      }
+      MailInstruction::SetReadCursor { index } => {
+        msg!("Instruction: SetReadCursor");
+        Self::process_set_read_cursor(accounts, index, program_id)
+      }
+      MailInstruction::MoveMail { mail_id, from, to } => {
+        msg!("Instruction: MoveMail");
+        Self::process_move_mail(accounts, &mail_id, from, to, program_id)
+      }
+      MailInstruction::SendToGroup { mail } => {
+        msg!("Instruction: SendToGroup");
+        Self::process_send_to_group(accounts, &mail, program_id)
+      }
+      MailInstruction::MigrateDates => {
+        msg!("Instruction: MigrateDates");
+        Self::process_migrate_dates(Self::account_at(accounts, 0)?, program_id)
+      }
+      MailInstruction::RecallMail { mail_id } => {
+        msg!("Instruction: RecallMail");
+        Self::process_recall_mail(accounts, &mail_id, program_id)
+      }
+      MailInstruction::MarkAllRead => {
+        msg!("Instruction: MarkAllRead");
+        Self::process_mark_all_read(accounts, program_id)
+      }
+      MailInstruction::DeleteMail { mail_id } => {
+        msg!("Instruction: DeleteMail");
+        Self::process_delete_mail(accounts, &mail_id, program_id)
+      }
+      MailInstruction::RestoreFromTrash { mail_id } => {
+        msg!("Instruction: RestoreFromTrash");
+        Self::process_restore_from_trash(accounts, &mail_id, program_id)
+      }
+      MailInstruction::EmptyTrash => {
+        msg!("Instruction: EmptyTrash");
+        Self::process_empty_trash(accounts, program_id)
+      }
+      MailInstruction::EditSentSubject {
+        mail_id,
+        new_subject,
+      } => {
+        msg!("Instruction: EditSentSubject");
+        Self::process_edit_sent_subject(accounts, &mail_id, new_subject, program_id)
+      }
+      MailInstruction::ValidateSend { mail } => {
+        msg!("Instruction: ValidateSend");
+        Self::process_validate_send(accounts, &mail, program_id)
+      }
+      MailInstruction::GetInboxPage { offset, limit } => {
+        msg!("Instruction: GetInboxPage");
+        Self::process_get_inbox_page(accounts, offset, limit, program_id)
+      }
+      MailInstruction::RepairHeader => {
+        msg!("Instruction: RepairHeader");
+        Self::process_repair_header(Self::account_at(accounts, 0)?, program_id)
+      }
+      MailInstruction::ScrubTail => {
+        msg!("Instruction: ScrubTail");
+        Self::process_scrub_tail(Self::account_at(accounts, 0)?, program_id)
+      }
+      MailInstruction::GetSentStatus { mail_id } => {
+        msg!("Instruction: GetSentStatus");
+        Self::process_get_sent_status(accounts, &mail_id, program_id)
+      }
+      MailInstruction::SetAutoArchive { days } => {
+        msg!("Instruction: SetAutoArchive");
+        Self::process_set_auto_archive(accounts, days, program_id)
+      }
+      MailInstruction::AutoArchive => {
+        msg!("Instruction: AutoArchive");
+        Self::process_auto_archive(Self::account_at(accounts, 0)?, program_id)
+      }
+      MailInstruction::ForwardMail {
+        mail_id,
+        forward_id,
+        to_address,
+        strip_attachments,
+      } => {
+        msg!("Instruction: ForwardMail");
+        Self::process_forward_mail(
+          accounts,
+          &mail_id,
+          forward_id,
+          to_address,
+          strip_attachments,
+          program_id,
+        )
+      }
+      MailInstruction::MergeMailbox { close_source } => {
+        msg!("Instruction: MergeMailbox");
+        Self::process_merge_mailbox(accounts, close_source, program_id)
+      }
+      MailInstruction::DeleteThread {
+        root_id,
+        include_sent,
+      } => {
+        msg!("Instruction: DeleteThread");
+        Self::process_delete_thread(accounts, &root_id, include_sent, program_id)
+      }
+      MailInstruction::ExportMailbox => {
+        msg!("Instruction: ExportMailbox");
+        Self::process_export_mailbox(accounts, program_id)
+      }
+      MailInstruction::ImportMailbox { merge } => {
+        msg!("Instruction: ImportMailbox");
+        Self::process_import_mailbox(accounts, merge, program_id)
+      }
+      MailInstruction::ScheduleMail { mail, deliver_at } => {
+        msg!("Instruction: ScheduleMail");
+        Self::process_schedule_mail(accounts, mail, deliver_at, program_id)
+      }
+      MailInstruction::ProcessScheduled => {
+        msg!("Instruction: ProcessScheduled");
+        Self::process_scheduled(accounts, program_id)
+      }
+      MailInstruction::CancelScheduled { mail_id } => {
+        msg!("Instruction: CancelScheduled");
+        Self::process_cancel_scheduled(accounts, &mail_id, program_id)
+      }
+      MailInstruction::AcknowledgeMail { mail_id } => {
+        msg!("Instruction: AcknowledgeMail");
+        Self::process_acknowledge_mail(accounts, &mail_id, program_id)
+      }
+      MailInstruction::SetRetention { max_age_secs } => {
+        msg!("Instruction: SetRetention");
+        Self::process_set_retention(accounts, max_age_secs, program_id)
+      }
+      MailInstruction::SetKeepSent { enabled } => {
+        msg!("Instruction: SetKeepSent");
+        Self::process_set_keep_sent(accounts, enabled, program_id)
+      }
+      MailInstruction::GetUnreadCount => {
+        msg!("Instruction: GetUnreadCount");
+        Self::process_get_unread_count(accounts, program_id)
+      }
+      MailInstruction::GetFolder {
+        folder,
+        start,
+        count,
+      } => {
+        msg!("Instruction: GetFolder");
+        Self::process_get_folder(accounts, folder, start, count, program_id)
+      }
+      MailInstruction::SetProfile { profile } => {
+        msg!("Instruction: SetProfile");
+        Self::process_set_profile(accounts, profile, program_id)
+      }
+      MailInstruction::GetStorageUsage => {
+        msg!("Instruction: GetStorageUsage");
+        Self::process_get_storage_usage(accounts, program_id)
+      }
+      MailInstruction::AddReaction { mail_id, emoji } => {
+        msg!("Instruction: AddReaction");
+        Self::process_add_reaction(accounts, &mail_id, emoji, program_id)
+      }
+      MailInstruction::SearchMailbox { query } => {
+        msg!("Instruction: SearchMailbox");
+        Self::process_search_mailbox(accounts, &query, program_id)
+      }
+      MailInstruction::GetMessageByIndex { folder, index } => {
+        msg!("Instruction: GetMessageByIndex");
+        Self::process_get_message_by_index(accounts, folder, index, program_id)
+      }
+      MailInstruction::ReplyAll { mail } => {
+        msg!("Instruction: ReplyAll");
+        Self::process_reply_all(accounts, &mail, program_id)
+      }
+      MailInstruction::SetGroupMembers { members } => {
+        msg!("Instruction: SetGroupMembers");
+        Self::process_set_group_members(accounts, members, program_id)
+      }
+      MailInstruction::SaveDraft { mail } => {
+        msg!("Instruction: SaveDraft");
+        Self::process_save_draft(accounts, mail, program_id)
+      }
+      MailInstruction::SetAllowlistMode { enabled } => {
+        msg!("Instruction: SetAllowlistMode");
+        Self::process_set_allowlist_mode(accounts, enabled, program_id)
       }
     }
   }
 
-  fn process_init_account(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+  /// Create the owner's mailbox at its program-derived address, unless the program already
+  /// owns it, and initialize it, seeding the inbox with a welcome mail when `welcome` is given.
+  /// A missing subject or body falls back to the SolMail greeting.
+  fn process_init_account(
+    accounts: &[AccountInfo],
+    welcome: Option<(Option<String>, Option<String>)>,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let account = &accounts[0];
     if !account.is_writable {
       return Err(NotWritable.into());
     }
 
-    if account.owner != program_id {
+    let owner_account = &accounts[1];
+    if !owner_account.is_signer {
+      return Err(Unauthorized.into());
+    }
+
+    let (address, bump) = find_mailbox_address(owner_account.key, program_id);
+    if address != *account.key {
+      return Err(InvalidMailboxAddress.into());
+    }
+
+    let system_account = &accounts[2];
+    if *system_account.key != system_program::id() {
       return Err(ProgramError::IncorrectProgramId);
     }
 
-    let welcome_mail = Mail {
+    if account.owner != program_id {
+      Self::create_mailbox(account, owner_account, system_account, bump, program_id)?;
+    } else if Self::is_initialized(account) {
+      return Err(AlreadyInitialized.into());
+    }
+
+    let inbox = welcome.map(|(subject, body)| Mail {
       id: String::from("00000000-0000-0000-0000-000000000000"),
       from_address: program_id.to_string(),
       to_address: account.key.to_string(),
-      subject: String::from("Welcome to SolMail"),
-      body: String::from("This is the start of your private messages on SolMail
-      Lorem, ipsum dolor sit amet consectetur adipisicing elit. Quos ut labore, debitis assumenda, dolorem nulla facere soluta exercitationem excepturi provident ipsam reprehenderit repellat quisquam corrupti commodi fugiat iusto quae voluptates!"),
-      sent_date: String::from("9/29/2021, 3:58:02 PM")
-    };
+      subject: subject.unwrap_or_else(|| String::from("Welcome to SolMail")),
+      body: body.unwrap_or_else(|| String::from("This is the start of your private messages on SolMail
+      Lorem, ipsum dolor sit amet consectetur adipisicing elit. Quos ut labore, debitis assumenda, dolorem nulla facere soluta exercitationem excepturi provident ipsam reprehenderit repellat quisquam corrupti commodi fugiat iusto quae voluptates!")),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    });
 
-    let mail_account = MailAccount {
-      inbox: vec![welcome_mail],
-      sent: Vec::new(),
+    let mut mail_account = MailAccount {
+      inbox: inbox.into_iter().collect(),
+      owner_pubkey: owner_account.key.to_string(),
+      ..MailAccount::default()
     };
 
-    let data_length = DataLength {
-      length: u32::try_from(get_instance_packed_len(&mail_account)?).unwrap(),
-    };
+    Self::store_mail_account(account, &mut mail_account)?;
 
-    let offset: usize = 4;
-    data_length.serialize(&mut &mut account.data.borrow_mut()[..offset])?;
-    mail_account.serialize(&mut &mut account.data.borrow_mut()[offset..])?;
+    Self::log_result("InitAccount", account);
 
     Ok(())
   }
 
+  /// Have the System Program create `account` at the owner's mailbox address, funded by the
+  /// owner and assigned to this program. An address someone funded beforehand cannot be
+  /// created again, so it is topped up to rent exemption and claimed in place instead.
+  fn create_mailbox<'a>(
+    account: &AccountInfo<'a>,
+    owner_account: &AccountInfo<'a>,
+    system_account: &AccountInfo<'a>,
+    bump: u8,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    let seeds: &[&[u8]] = &[MAILBOX_SEED, owner_account.key.as_ref(), &[bump]];
+    let lamports = Rent::get()?.minimum_balance(MAILBOX_LEN);
+    let space = u64::try_from(MAILBOX_LEN).unwrap();
+
+    if account.lamports() == 0 {
+      return invoke_signed(
+        &system_instruction::create_account(
+          owner_account.key,
+          account.key,
+          lamports,
+          space,
+          program_id,
+        ),
+        &[
+          owner_account.clone(),
+          account.clone(),
+          system_account.clone(),
+        ],
+        &[seeds],
+      );
+    }
+
+    let top_up = lamports.saturating_sub(account.lamports());
+    if top_up > 0 {
+      invoke(
+        &system_instruction::transfer(owner_account.key, account.key, top_up),
+        &[
+          owner_account.clone(),
+          account.clone(),
+          system_account.clone(),
+        ],
+      )?;
+    }
+
+    invoke_signed(
+      &system_instruction::allocate(account.key, space),
+      &[account.clone(), system_account.clone()],
+      &[seeds],
+    )?;
+    invoke_signed(
+      &system_instruction::assign(account.key, program_id),
+      &[account.clone(), system_account.clone()],
+      &[seeds],
+    )
+  }
+
   fn process_send_mail(
     accounts: &[AccountInfo],
     mail: &Mail,
+    require_receiver_signature: bool,
+    nonce: u64,
+    verify_signature: bool,
+    normalize_subject: bool,
     program_id: &Pubkey,
   ) -> ProgramResult {
+    let normalized;
+    let staged = if normalize_subject {
+      normalized = Self::normalize_subject(mail)?;
+      &normalized
+    } else {
+      mail
+    };
+
+    let mut mailboxes = Self::stage_send(accounts, staged, program_id)?;
+
     let sender_account = &accounts[0];
+    let receiver_account = &accounts[1];
 
-    if !sender_account.is_writable {
-      return Err(NotWritable.into());
+    let key = &mail.idempotency_key;
+    if !key.is_empty() && mailboxes.get_mut(sender_account)?.recent_keys.contains(key) {
+      msg!("Duplicate send skipped");
+      Self::log_result("SendMail", sender_account);
+      return Ok(());
     }
 
-    if sender_account.owner != program_id {
-      return Err(ProgramError::IncorrectProgramId);
+    let mut optional_accounts = accounts[3..].iter();
+
+    if require_receiver_signature {
+      let receiver_owner = optional_accounts.next().ok_or(ReceiverConsentRequired)?;
+      let receiver_data = mailboxes.get_mut(receiver_account)?;
+      if !receiver_owner.is_signer || receiver_owner.key.to_string() != receiver_data.owner_pubkey {
+        return Err(ReceiverConsentRequired.into());
+      }
     }
 
-    let receiver_account = &accounts[1];
+    if verify_signature {
+      let instructions_account = optional_accounts
+        .next()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+      let owner_pubkey = mailboxes.get_mut(sender_account)?.owner_pubkey.clone();
+      Self::verify_signature(mail, &owner_pubkey, instructions_account)?;
+    }
 
-    if !receiver_account.is_writable {
-      return Err(NotWritable.into());
+    let sender_data = mailboxes.get_mut(sender_account)?;
+    if nonce != sender_data.send_nonce {
+      return Err(InvalidNonce.into());
     }
+    sender_data.send_nonce += 1;
 
-    if receiver_account.owner != program_id {
-      return Err(ProgramError::IncorrectProgramId);
+    if !key.is_empty() {
+      sender_data.record_key(key.clone());
     }
 
-    let offset: usize = 4;
+    mailboxes.commit()?;
 
-    let data_length = DataLength::try_from_slice(&sender_account.data.borrow()[..offset])?;
+    Self::log_result("SendMail", sender_account);
 
-    let mut sender_data;
-    if data_length.length > 0 {
-      let length = usize::try_from(data_length.length + u32::try_from(offset).unwrap()).unwrap();
-      sender_data = MailAccount::try_from_slice(&sender_account.data.borrow()[offset..length])?;
-    } else {
-      sender_data = MailAccount {
-        inbox: Vec::new(),
-        sent: Vec::new(),
-      };
+    Ok(())
+  }
+
+  fn process_validate_send(
+    accounts: &[AccountInfo],
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::stage_send(accounts, mail, program_id)?.check()?;
+
+    msg!("VALIDATE_OK");
+
+    Self::log_result("ValidateSend", &accounts[0]);
+
+    Ok(())
+  }
+
+  fn process_forward_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    forward_id: String,
+    to_address: String,
+    strip_attachments: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+
+    let receiver_account = &accounts[1];
+    if receiver_account.key.to_string() != to_address {
+      return Err(InvalidAddress.into());
     }
 
-    sender_data.sent.push(mail.clone());
-    let data_length = DataLength {
-      length: u32::try_from(get_instance_packed_len(&sender_data)?).unwrap(),
+    let sender_data = Self::load_mail_account(sender_account)?;
+    let folder = sender_data.contains_id(mail_id).ok_or(MailNotFound)?;
+    let original = sender_data
+      .folder(folder)
+      .iter()
+      .find(|mail| mail.id == mail_id)
+      .cloned()
+      .ok_or(MailNotFound)?;
+
+    let forwarded = Mail {
+      id: forward_id,
+      from_address: sender_account.key.to_string(),
+      to_address,
+      subject: format!("Fwd: {}", original.subject),
+      in_reply_to: None,
+      read: false,
+      bcc: Vec::new(),
+      labels: Vec::new(),
+      attachments: if strip_attachments {
+        Vec::new()
+      } else {
+        original.attachments
+      },
+      delivered_at: 0,
+      signature: Vec::new(),
+      idempotency_key: String::new(),
+      reactions: Vec::new(),
+      acknowledged: false,
+      sent_date: String::new(),
+      sent_at: Self::current_timestamp()?,
+      expires_at: None,
+      ..original
     };
-    data_length.serialize(&mut &mut sender_account.data.borrow_mut()[..offset])?;
-    sender_data.serialize(&mut &mut sender_account.data.borrow_mut()[offset..])?;
 
-    let data_length = DataLength::try_from_slice(&receiver_account.data.borrow()[..offset])?;
+    Self::stage_send(accounts, &forwarded, program_id)?.commit()?;
 
-    let mut receiver_data;
-    if data_length.length > 0 {
-      let length = usize::try_from(data_length.length + u32::try_from(offset).unwrap()).unwrap();
-      receiver_data = MailAccount::try_from_slice(&receiver_account.data.borrow()[offset..length])?;
-    } else {
-      receiver_data = MailAccount {
-        inbox: Vec::new(),
-        sent: Vec::new(),
-      }
+    Self::log_result("ForwardMail", sender_account);
+
+    Ok(())
+  }
+
+  fn process_merge_mailbox(
+    accounts: &[AccountInfo],
+    close_source: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let source_account = &accounts[0];
+    Self::check_account(source_account, program_id)?;
+
+    let destination_account = &accounts[1];
+    Self::check_account(destination_account, program_id)?;
+
+    if source_account.key == destination_account.key {
+      return Err(ProgramError::InvalidArgument);
     }
-    receiver_data.inbox.push(mail.clone());
 
-    let data_length = DataLength {
-      length: u32::try_from(get_instance_packed_len(&receiver_data)?).unwrap(),
-    };
-    data_length.serialize(&mut &mut receiver_account.data.borrow_mut()[..offset])?;
-    receiver_data.serialize(&mut &mut receiver_account.data.borrow_mut()[offset..])?;
+    let owner_account = &accounts[2];
+
+    let source_data = Self::load_mail_account(source_account)?;
+    let mut destination_data = Self::load_mail_account(destination_account)?;
+
+    let owner = owner_account.key.to_string();
+    if !owner_account.is_signer
+      || source_data.owner_pubkey != owner
+      || destination_data.owner_pubkey != owner
+    {
+      return Err(Unauthorized.into());
+    }
+
+    destination_data.merge_from(source_data);
+    Self::store_mail_account(destination_account, &mut destination_data)?;
+
+    if close_source {
+      let lamports = source_account.lamports();
+      **source_account.lamports.borrow_mut() = 0;
+      **destination_account.lamports.borrow_mut() = destination_account
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ArithmeticOverflow)?;
+      source_account.data.borrow_mut().fill(0);
+    }
+
+    Self::log_result("MergeMailbox", destination_account);
 
     Ok(())
   }
-}
 
-// Sanity tests
-#[cfg(test)]
-mod test {
-  use super::*;
-  use solana_program::clock::Epoch;
+  /// The mail with its subject passed through `normalize_text`.
+  #[cfg(feature = "normalize")]
+  fn normalize_subject(mail: &Mail) -> Result<Mail, ProgramError> {
+    Ok(Mail {
+      subject: normalize_text(&mail.subject),
+      ..mail.clone()
+    })
+  }
 
-  #[test]
-  fn test_init_account() {
-    let program_id = Pubkey::default();
-    let key = Pubkey::default();
-    let mut lamports = 0;
-    let mut data = [0; 1000];
+  /// Subject normalization is left out of builds without the `normalize` feature.
+  #[cfg(not(feature = "normalize"))]
+  fn normalize_subject(_mail: &Mail) -> Result<Mail, ProgramError> {
+    Err(MailError::InvalidInstruction.into())
+  }
 
-    let account = AccountInfo::new(
-      &key,
-      true,
-      true,
-      &mut lamports,
-      &mut data,
-      &program_id,
-      false,
-      Epoch::default(),
-    );
+  /// Check a single-receiver send and apply it to in-memory mailboxes, leaving the caller to
+  /// commit them.
+  fn stage_send<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> Result<Mailboxes<'a, 'b>, ProgramError> {
+    Self::check_accounts_len(accounts, 3)?;
 
-    Processor::process_init_account(&account, &program_id).unwrap();
+    mail.validate()?;
 
-    let data_length = DataLength::try_from_slice(&account.data.borrow()[..4]).unwrap();
-    let mail_account = MailAccount::try_from_slice(
-      &account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
-    )
-    .unwrap();
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+    Self::check_initialized(sender_account, AccountNotInitialized)?;
+
+    let receiver_account = &accounts[1];
+    Self::check_account(receiver_account, program_id)?;
+    Self::check_initialized(receiver_account, ReceiverNotInitialized)?;
+
+    if mail.to_address != receiver_account.key.to_string() {
+      return Err(RecipientMismatch.into());
+    }
+
+    let owner_account = &accounts[2];
 
-    assert_eq!(mail_account.inbox[0].subject, "Welcome to SolMail");
+    let now = Self::current_timestamp()?;
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+
+    mailboxes.record_sent(sender_account, mail.clone())?;
+
+    let sender_data = mailboxes.get_mut(sender_account)?;
+    sender_data
+      .pending
+      .retain(|(_, sent_at)| now.saturating_sub(*sent_at) <= UNDO_WINDOW_SECS);
+    sender_data.pending.push((mail.clone(), now));
+
+    mailboxes.deliver(sender_account, receiver_account, mail.clone())?;
+
+    Ok(mailboxes)
   }
 
-  #[test]
-  fn test_send_mail() {
-    let program_id = Pubkey::default();
-    let key = Pubkey::default();
-    let mut lamports = 0;
-    let mut sender_data = [0; 1000];
+  fn process_schedule_mail(
+    accounts: &[AccountInfo],
+    mail: Mail,
+    deliver_at: u64,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    mail.validate()?;
 
-    let sender_account = AccountInfo::new(
-      &key,
-      true,
-      true,
-      &mut lamports,
-      &mut sender_data,
-      &program_id,
-      false,
-      Epoch::default(),
-    );
+    Self::check_accounts_len(accounts, 2)?;
 
-    let mut receiver_data = [0; 1000];
-    let mut lamports = 0;
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+    Self::check_initialized(sender_account, AccountNotInitialized)?;
 
-    let receiver_account = AccountInfo::new(
-      &key,
-      true,
-      true,
-      &mut lamports,
-      &mut receiver_data,
-      &program_id,
-      false,
-      Epoch::default(),
-    );
+    let owner_account = &accounts[1];
 
-    let accounts = vec![sender_account.clone(), receiver_account.clone()];
+    let mut mail_account = Self::load_mail_account(sender_account)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+    Self::check_sender(sender_account, &mail)?;
+    Pubkey::from_str(&mail.to_address).map_err(|_| InvalidAddress)?;
 
-    let mail = Mail {
-      id: String::from("00000000-0000-0000-0000-000000000000"),
-      from_address: sender_account.key.to_string(),
-      to_address: receiver_account.key.to_string(),
-      subject: String::from("Hey Mike!!!"),
-      body: String::from(
-        "Lorem, ipsum dolor sit amet consectetur adipisicing elit. Quos ut labore, debitis assumenda, dolorem nulla facere soluta exercitationem excepturi provident ipsam reprehenderit repellat quisquam corrupti commodi fugiat iusto quae voluptates!"
-      ),
-      sent_date: String::from("9/29/2021, 3:58:02 PM")
-    };
+    mail_account.scheduled.push((mail, deliver_at));
 
-    Processor::process_send_mail(&accounts, &mail, &program_id).unwrap();
+    Self::store_mail_account(sender_account, &mut mail_account)?;
 
-    let data_length = DataLength::try_from_slice(&sender_account.data.borrow()[..4]).unwrap();
-    let mail_account = MailAccount::try_from_slice(
-      &sender_account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
-    )
-    .unwrap();
+    Self::log_result("ScheduleMail", sender_account);
 
-    assert_eq!(mail_account.sent[0].subject, "Hey Mike!!!");
+    Ok(())
+  }
 
-    let data_length = DataLength::try_from_slice(&receiver_account.data.borrow()[..4]).unwrap();
-    let mail_account = MailAccount::try_from_slice(
-      &receiver_account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
-    )
-    .unwrap();
+  fn process_scheduled(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    let (sender_account, receiver_accounts) = accounts.split_first().ok_or(NotEnoughAccounts)?;
+    Self::check_account(sender_account, program_id)?;
+    Self::check_initialized(sender_account, AccountNotInitialized)?;
 
-    assert_eq!(mail_account.inbox[0].subject, "Hey Mike!!!");
+    let now = Self::current_timestamp()?;
+
+    let mut mailboxes = Mailboxes::default();
+
+    let queue = std::mem::take(&mut mailboxes.get_mut(sender_account)?.scheduled);
+    let mut receiver_accounts = receiver_accounts.iter();
+    let mut handled = 0;
+    let mut kept = Vec::with_capacity(queue.len());
+
+    for (mail, deliver_at) in queue {
+      if deliver_at > now || handled == MAX_RECIPIENTS {
+        kept.push((mail, deliver_at));
+        continue;
+      }
+      handled += 1;
+
+      let receiver_account = receiver_accounts.next().ok_or(NotEnoughAccounts)?;
+      let checked = Self::check_account(receiver_account, program_id)
+        .and_then(|_| {
+          if mail.to_address == receiver_account.key.to_string() {
+            Ok(())
+          } else {
+            Err(RecipientMismatch.into())
+          }
+        })
+        .and_then(|_| mailboxes.check_deliverable(sender_account, receiver_account, &mail));
+
+      if let Err(error) = checked {
+        msg!("Kept {} queued: {:?}", mail.id, error);
+        kept.push((mail, deliver_at));
+        continue;
+      }
+
+      mailboxes.record_sent(sender_account, mail.clone())?;
+      mailboxes.deliver(sender_account, receiver_account, mail)?;
+    }
+
+    mailboxes.get_mut(sender_account)?.scheduled = kept;
+    mailboxes.commit()?;
+
+    Self::log_result("ProcessScheduled", sender_account);
+
+    Ok(())
+  }
+
+  fn process_cancel_scheduled(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(sender_account)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let position = mail_account
+      .scheduled
+      .iter()
+      .position(|(mail, _)| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+    mail_account.scheduled.remove(position);
+
+    Self::store_mail_account(sender_account, &mut mail_account)?;
+
+    Self::log_result("CancelScheduled", sender_account);
+
+    Ok(())
+  }
+
+  fn process_undo_send(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+
+    let receiver_account = &accounts[1];
+    Self::check_account(receiver_account, program_id)?;
+
+    let owner_account = &accounts[2];
+
+    let now = Self::current_timestamp()?;
+
+    let mut mailboxes = Mailboxes::default();
+
+    let sender_data = mailboxes.get_mut(sender_account)?;
+    Self::check_owner_signer(owner_account, sender_data)?;
+
+    let pending_position = sender_data
+      .pending
+      .iter()
+      .position(|(mail, _)| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+    let (pending_mail, sent_at) = sender_data.pending.remove(pending_position);
+
+    if receiver_account.key.to_string() != pending_mail.to_address {
+      return Err(RecipientMismatch.into());
+    }
+
+    if now.saturating_sub(sent_at) > UNDO_WINDOW_SECS {
+      return Err(UndoWindowExpired.into());
+    }
+
+    if let Some(sent_position) = sender_data.sent.iter().rposition(|mail| mail.id == mail_id) {
+      sender_data.sent.remove(sent_position);
+    }
+
+    let receiver_data = mailboxes.get_mut(receiver_account)?;
+    let inbox_position = receiver_data
+      .inbox
+      .iter()
+      .rposition(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    if receiver_data.inbox[inbox_position].from_address != sender_account.key.to_string() {
+      return Err(Unauthorized.into());
+    }
+
+    receiver_data.inbox.remove(inbox_position);
+
+    mailboxes.commit()?;
+
+    Self::log_result("UndoSend", sender_account);
+
+    Ok(())
+  }
+
+  fn process_recall_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+
+    let receiver_account = &accounts[1];
+    Self::check_account(receiver_account, program_id)?;
+
+    let owner_account = &accounts[2];
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+
+    let receiver_data = mailboxes.get_mut(receiver_account)?;
+    let inbox_position = receiver_data
+      .inbox
+      .iter()
+      .rposition(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    if receiver_data.inbox[inbox_position].from_address != sender_account.key.to_string() {
+      return Err(Unauthorized.into());
+    }
+
+    if receiver_data.inbox[inbox_position].read {
+      return Err(AlreadyRead.into());
+    }
+
+    receiver_data.inbox.remove(inbox_position);
+
+    let sender_data = mailboxes.get_mut(sender_account)?;
+    if let Some(sent_position) = sender_data.sent.iter().rposition(|mail| mail.id == mail_id) {
+      sender_data.sent.remove(sent_position);
+    }
+    sender_data.pending.retain(|(mail, _)| mail.id != mail_id);
+
+    mailboxes.commit()?;
+
+    Self::log_result("RecallMail", sender_account);
+
+    Ok(())
+  }
+
+  /// Deliver to the receivers in `accounts[2..]` order, so every validator writes the same
+  /// accounts in the same sequence.
+  fn process_send_mail_bcc(
+    accounts: &[AccountInfo],
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    mail.validate()?;
+
+    Self::check_accounts_len(accounts, 2)?;
+    let (sender_account, owner_account, receiver_accounts) =
+      (&accounts[0], &accounts[1], &accounts[2..]);
+
+    if receiver_accounts.is_empty() {
+      return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    Self::check_recipient_count(receiver_accounts.len())?;
+
+    Self::check_account(sender_account, program_id)?;
+
+    for receiver_account in receiver_accounts {
+      Self::check_account(receiver_account, program_id)?;
+    }
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+
+    let sent_copy = Mail {
+      bcc: receiver_accounts
+        .iter()
+        .map(|receiver_account| receiver_account.key.to_string())
+        .collect(),
+      ..mail.clone()
+    };
+    mailboxes.record_sent(sender_account, sent_copy)?;
+
+    for receiver_account in receiver_accounts {
+      let receiver_copy = Mail {
+        to_address: receiver_account.key.to_string(),
+        bcc: Vec::new(),
+        ..mail.clone()
+      };
+      mailboxes.deliver(sender_account, receiver_account, receiver_copy)?;
+    }
+
+    mailboxes.commit()?;
+
+    Self::log_result("SendMailBcc", sender_account);
+
+    Ok(())
+  }
+
+  /// Deliver a reply to every participant of the original mail, which must be in the
+  /// replier's mailbox and whose participants must be passed in `Mail::participants` order.
+  fn process_reply_all(
+    accounts: &[AccountInfo],
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    mail.validate()?;
+
+    Self::check_accounts_len(accounts, 2)?;
+    let (replier_account, owner_account, participant_accounts) =
+      (&accounts[0], &accounts[1], &accounts[2..]);
+
+    Self::check_recipient_count(participant_accounts.len())?;
+
+    Self::check_account(replier_account, program_id)?;
+
+    for participant_account in participant_accounts {
+      Self::check_account(participant_account, program_id)?;
+    }
+
+    let original_id = mail.in_reply_to.as_deref().ok_or(MailNotFound)?;
+
+    let mut mailboxes = Mailboxes::default();
+
+    let replier_data = mailboxes.get_mut(replier_account)?;
+    Self::check_owner_signer(owner_account, replier_data)?;
+    let participants = replier_data
+      .contains_id(original_id)
+      .and_then(|folder| {
+        replier_data
+          .folder(folder)
+          .iter()
+          .find(|original| original.id == original_id)
+      })
+      .ok_or(MailNotFound)?
+      .participants(&replier_account.key.to_string());
+
+    if participants.is_empty()
+      || participants.len() != participant_accounts.len()
+      || participants
+        .iter()
+        .zip(participant_accounts)
+        .any(|(participant, account)| *participant != account.key.to_string())
+    {
+      return Err(ParticipantMismatch.into());
+    }
+
+    let sent_copy = Mail {
+      bcc: participants,
+      ..mail.clone()
+    };
+    mailboxes.record_sent(replier_account, sent_copy)?;
+
+    for participant_account in participant_accounts {
+      let participant_copy = Mail {
+        to_address: participant_account.key.to_string(),
+        bcc: Vec::new(),
+        ..mail.clone()
+      };
+      mailboxes.deliver(replier_account, participant_account, participant_copy)?;
+    }
+
+    mailboxes.commit()?;
+
+    Self::log_result("ReplyAll", replier_account);
+
+    Ok(())
+  }
+
+  fn process_send_batch(
+    accounts: &[AccountInfo],
+    mails: &[Mail],
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+    let (sender_account, owner_account, receiver_accounts) =
+      (&accounts[0], &accounts[1], &accounts[2..]);
+
+    if mails.len() != receiver_accounts.len() {
+      return Err(AccountMailCountMismatch.into());
+    }
+
+    Self::check_recipient_count(receiver_accounts.len())?;
+
+    for mail in mails {
+      mail.validate()?;
+    }
+
+    Self::check_account(sender_account, program_id)?;
+
+    for (mail, receiver_account) in mails.iter().zip(receiver_accounts) {
+      Self::check_account(receiver_account, program_id)?;
+
+      if mail.to_address != receiver_account.key.to_string() {
+        return Err(RecipientMismatch.into());
+      }
+    }
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+
+    for (mail, receiver_account) in mails.iter().zip(receiver_accounts) {
+      mailboxes.record_sent(sender_account, mail.clone())?;
+      mailboxes.deliver(sender_account, receiver_account, mail.clone())?;
+    }
+
+    mailboxes.commit()?;
+
+    Self::log_result("SendBatch", sender_account);
+
+    Ok(())
+  }
+
+  /// Send each mail whose receiver passes the checks of a strict batch, skipping the rest,
+  /// and write which receivers got their mail into the trailing scratch account.
+  fn process_send_batch_best_effort(
+    accounts: &[AccountInfo],
+    mails: &[Mail],
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+    let (sender_account, owner_account) = (&accounts[0], &accounts[1]);
+    let (scratch_account, receiver_accounts) =
+      accounts[2..].split_last().ok_or(NotEnoughAccounts)?;
+
+    if mails.len() != receiver_accounts.len() {
+      return Err(AccountMailCountMismatch.into());
+    }
+
+    Self::check_recipient_count(receiver_accounts.len())?;
+
+    Self::check_account(sender_account, program_id)?;
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+    let mut results = Vec::with_capacity(mails.len());
+
+    for (mail, receiver_account) in mails.iter().zip(receiver_accounts) {
+      let checked = mail
+        .validate()
+        .map_err(ProgramError::from)
+        .and_then(|_| Self::check_account(receiver_account, program_id))
+        .and_then(|_| {
+          if mail.to_address == receiver_account.key.to_string() {
+            Ok(())
+          } else {
+            Err(RecipientMismatch.into())
+          }
+        })
+        .and_then(|_| Self::check_sender(sender_account, mail))
+        .and_then(|_| mailboxes.check_deliverable(sender_account, receiver_account, mail));
+
+      if let Err(error) = checked {
+        msg!("Skipped {}: {:?}", receiver_account.key, error);
+        results.push((receiver_account.key.to_string(), false));
+        continue;
+      }
+
+      mailboxes.record_sent(sender_account, mail.clone())?;
+      mailboxes.deliver(sender_account, receiver_account, mail.clone())?;
+      results.push((receiver_account.key.to_string(), true));
+    }
+
+    mailboxes.commit()?;
+
+    Self::write_account(scratch_account, &results)?;
+
+    Self::log_result("SendBatch", sender_account);
+
+    Ok(())
+  }
+
+  fn process_send_to_group(
+    accounts: &[AccountInfo],
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    mail.validate()?;
+
+    if accounts.len() < 3 {
+      return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let sender_account = &accounts[0];
+    Self::check_account(sender_account, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let group_account = &accounts[2];
+    Self::check_owner(group_account, program_id)?;
+
+    let member_accounts = &accounts[3..];
+    Self::check_recipient_count(member_accounts.len())?;
+
+    let group: GroupAccount = Self::load_with_header(group_account)?;
+
+    if group.members.len() != member_accounts.len()
+      || group
+        .members
+        .iter()
+        .zip(member_accounts)
+        .any(|(member, member_account)| *member != member_account.key.to_string())
+    {
+      return Err(GroupMemberMismatch.into());
+    }
+
+    for member_account in member_accounts {
+      Self::check_account(member_account, program_id)?;
+    }
+
+    let mut mailboxes = Mailboxes::default();
+
+    Self::check_owner_signer(owner_account, mailboxes.get_mut(sender_account)?)?;
+
+    mailboxes.record_sent(sender_account, mail.clone())?;
+
+    for member_account in member_accounts {
+      mailboxes.deliver(sender_account, member_account, mail.clone())?;
+    }
+
+    mailboxes.commit()?;
+
+    Self::log_result("SendToGroup", sender_account);
+
+    Ok(())
+  }
+
+  fn process_set_group_members(
+    accounts: &[AccountInfo],
+    members: Vec<String>,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let group_account = &accounts[0];
+    Self::check_account(group_account, program_id)?;
+
+    let owner_account = &accounts[1];
+    let owner = owner_account.key.to_string();
+
+    let mut group = if Self::is_initialized(group_account) {
+      Self::load_with_header::<GroupAccount>(group_account)?
+    } else {
+      // Only the holder of the group account's key may claim it, so nobody can front-run
+      // its creator.
+      if !group_account.is_signer {
+        return Err(Unauthorized.into());
+      }
+      GroupAccount {
+        owner: owner.clone(),
+        ..GroupAccount::default()
+      }
+    };
+
+    if !owner_account.is_signer || group.owner != owner {
+      return Err(Unauthorized.into());
+    }
+
+    Self::check_recipient_count(members.len())?;
+
+    for member in &members {
+      Pubkey::from_str(member).map_err(|_| InvalidAddress)?;
+    }
+
+    group.members = members;
+
+    Self::write_account(group_account, &group)?;
+
+    Self::log_result("SetGroupMembers", group_account);
+
+    Ok(())
+  }
+
+  fn process_save_draft(
+    accounts: &[AccountInfo],
+    mail: Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    mail.validate()?;
+
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if mail_account.drafts.iter().any(|draft| draft.id == mail.id) {
+      return Err(DuplicateDraft.into());
+    }
+
+    mail_account.drafts.push(mail);
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SaveDraft", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_update_draft(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    mail: &Mail,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    if mail.id != mail_id {
+      return Err(MailIdMismatch.into());
+    }
+
+    mail.validate()?;
+
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let draft = mail_account
+      .drafts
+      .iter_mut()
+      .find(|draft| draft.id == mail_id)
+      .ok_or(MailNotFound)?;
+    *draft = mail.clone();
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("UpdateDraft", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_edit_sent_subject(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    new_subject: String,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let mail = mail_account
+      .sent
+      .iter_mut()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    let edited = Mail {
+      subject: new_subject,
+      ..mail.clone()
+    };
+    edited.validate()?;
+
+    *mail = edited;
+    mail.stamp_byte_size();
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("EditSentSubject", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_delete_all_read(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let inbox_len = mail_account.inbox.len();
+    mail_account.inbox.retain(|mail| !mail.read);
+    let removed = inbox_len - mail_account.inbox.len();
+
+    msg!("Removed {} read messages", removed);
+
+    if removed == 0 {
+      Self::log_result("DeleteAllRead", mail_account_info);
+      return Ok(());
+    }
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("DeleteAllRead", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_mark_all_read(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if mail_account.inbox.iter().all(|mail| mail.read) {
+      Self::log_result("MarkAllRead", mail_account_info);
+      return Ok(());
+    }
+
+    for mail in mail_account.inbox.iter_mut() {
+      mail.read = true;
+    }
+    mail_account.last_read_index = u32::try_from(mail_account.inbox.len() - 1).unwrap();
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("MarkAllRead", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_delete_thread(
+    accounts: &[AccountInfo],
+    root_id: &str,
+    include_sent: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let thread = mail_account.thread_for(root_id);
+    if !thread.iter().any(|mail| mail.id == root_id) {
+      return Err(MailNotFound.into());
+    }
+    let ids: BTreeSet<String> = thread.iter().map(|mail| mail.id.clone()).collect();
+
+    mail_account.inbox.retain(|mail| !ids.contains(&mail.id));
+    if include_sent {
+      mail_account.sent.retain(|mail| !ids.contains(&mail.id));
+    }
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("DeleteThread", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_acknowledge_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let receiver_account = &accounts[0];
+    Self::check_account(receiver_account, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mailboxes = Mailboxes::default();
+
+    let receiver_data = mailboxes.get_mut(receiver_account)?;
+    Self::check_owner_signer(owner_account, receiver_data)?;
+
+    let mail = receiver_data
+      .inbox
+      .iter_mut()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+    mail.acknowledged = true;
+    let from_address = mail.from_address.clone();
+
+    if let Some(sender_account) = accounts.get(2) {
+      Self::check_account(sender_account, program_id)?;
+
+      if sender_account.key.to_string() != from_address {
+        return Err(InvalidAddress.into());
+      }
+
+      let sender_data = mailboxes.get_mut(sender_account)?;
+      if let Some(sent) = sender_data.sent.iter_mut().find(|mail| mail.id == mail_id) {
+        sent.acknowledged = true;
+      }
+    }
+
+    mailboxes.commit()?;
+
+    Self::log_result("AcknowledgeMail", receiver_account);
+
+    Ok(())
+  }
+
+  fn process_add_reaction(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    emoji: String,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let reactor_account = &accounts[1];
+    if !reactor_account.is_signer {
+      return Err(Unauthorized.into());
+    }
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+
+    let folder = mail_account.contains_id(mail_id).ok_or(MailNotFound)?;
+    let mail = mail_account
+      .folder_mut(folder)
+      .iter_mut()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+    mail.react(reactor_account.key.to_string(), emoji)?;
+    mail.stamp_byte_size();
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("AddReaction", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_purge_expired(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let now = Self::current_timestamp()?;
+    let mut mail_account = Self::load_mail_account(account)?;
+
+    mail_account.inbox.retain(|mail| !mail.is_expired(now));
+    mail_account.sent.retain(|mail| !mail.is_expired(now));
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("PurgeExpired", account);
+
+    Ok(())
+  }
+
+  fn process_migrate_dates(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let mut mail_account = match Self::load_mail_account(account) {
+      Ok(mail_account) => mail_account,
+      Err(_) => MailAccount::from(Self::load_with_header::<LegacyMailAccount>(account)?),
+    };
+
+    mail_account.migrate_dates();
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("MigrateDates", account);
+
+    Ok(())
+  }
+
+  fn process_add_contact(
+    accounts: &[AccountInfo],
+    alias: String,
+    address: String,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Pubkey::from_str(&address).map_err(|_| InvalidAddress)?;
+
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if mail_account
+      .contacts
+      .iter()
+      .any(|(existing, _)| *existing == alias)
+    {
+      return Err(DuplicateContact.into());
+    }
+
+    mail_account.contacts.push((alias, address));
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("AddContact", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_remove_contact(
+    accounts: &[AccountInfo],
+    alias: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    let position = mail_account
+      .contacts
+      .iter()
+      .position(|(existing, _)| existing == alias)
+      .ok_or(ContactNotFound)?;
+    mail_account.contacts.remove(position);
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("RemoveContact", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_stats(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let stats = Self::load_mail_account(mail_account_info)?.stats();
+
+    Self::write_account(scratch_account, &stats)?;
+
+    Self::log_result("GetStats", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_unread_count(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 1)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let unread = Self::load_mail_account(mail_account_info)?.unread_count();
+
+    match accounts.get(1) {
+      Some(scratch_account) => {
+        Self::check_scratch(scratch_account, program_id)?;
+        Self::write_account(scratch_account, &unread)?;
+      }
+      None => msg!("UNREAD {}", unread),
+    }
+
+    Self::log_result("GetUnreadCount", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_storage_usage(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let total_bytes = Self::load_mail_account(mail_account_info)?.total_bytes();
+
+    Self::write_account(scratch_account, &total_bytes)?;
+
+    Self::log_result("GetStorageUsage", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_add_label(
+    account: &AccountInfo,
+    mail_id: &str,
+    label: String,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let mut mail_account = Self::load_mail_account(account)?;
+
+    let mail = mail_account
+      .inbox
+      .iter_mut()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    if mail.labels.contains(&label) {
+      Self::log_result("AddLabel", account);
+      return Ok(());
+    }
+
+    if mail.labels.len() >= MAX_LABELS {
+      return Err(TooManyLabels.into());
+    }
+
+    mail.labels.push(label);
+    mail.stamp_byte_size();
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("AddLabel", account);
+
+    Ok(())
+  }
+
+  fn process_remove_label(
+    account: &AccountInfo,
+    mail_id: &str,
+    label: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let mut mail_account = Self::load_mail_account(account)?;
+
+    let mail = mail_account
+      .inbox
+      .iter_mut()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+    mail.labels.retain(|existing| existing != label);
+    mail.stamp_byte_size();
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("RemoveLabel", account);
+
+    Ok(())
+  }
+
+  fn process_get_message(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mail_account = Self::load_mail_account(mail_account_info)?;
+
+    let mail = mail_account
+      .inbox
+      .iter()
+      .chain(mail_account.sent.iter())
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    Self::write_account(scratch_account, mail)?;
+
+    Self::log_result("GetMessage", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_message_by_index(
+    accounts: &[AccountInfo],
+    folder: Folder,
+    index: u32,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mail_account = Self::load_mail_account(mail_account_info)?;
+
+    let mail = mail_account
+      .folder(folder)
+      .get(usize::try_from(index).unwrap())
+      .ok_or(IndexOutOfBounds)?;
+
+    Self::write_account(scratch_account, mail)?;
+
+    Self::log_result("GetMessageByIndex", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_inbox_page(
+    accounts: &[AccountInfo],
+    offset: u32,
+    limit: u32,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mail_account = Self::load_mail_account(mail_account_info)?;
+
+    Self::write_account(scratch_account, &mail_account.inbox_page(offset, limit))?;
+
+    Self::log_result("GetInboxPage", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_folder(
+    accounts: &[AccountInfo],
+    folder: Folder,
+    start: u32,
+    count: u32,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mail_account = Self::load_mail_account(mail_account_info)?;
+
+    Self::write_account(
+      scratch_account,
+      &mail_account.folder_page(folder, start, count),
+    )?;
+
+    Self::log_result("GetFolder", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_search_mailbox(
+    accounts: &[AccountInfo],
+    query: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let matches = Self::load_mail_account(mail_account_info)?.search(query);
+
+    Self::write_account(scratch_account, &matches)?;
+
+    Self::log_result("SearchMailbox", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_get_sent_status(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let sender_account = &accounts[0];
+    Self::check_owner(sender_account, program_id)?;
+
+    let receiver_account = &accounts[1];
+    Self::check_owner(receiver_account, program_id)?;
+
+    let scratch_account = &accounts[2];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let sender_data = Self::load_mail_account(sender_account)?;
+    let sent = sender_data
+      .sent
+      .iter()
+      .find(|mail| mail.id == mail_id)
+      .ok_or(MailNotFound)?;
+
+    let receiver_data = Self::load_mail_account(receiver_account)?;
+    let status = receiver_data
+      .inbox
+      .iter()
+      .find(|mail| mail.id == mail_id && mail.from_address == sent.from_address)
+      .map_or(SentStatus::default(), |mail| SentStatus {
+        delivered: true,
+        read: mail.read,
+      });
+
+    Self::write_account(scratch_account, &status)?;
+
+    Self::log_result("GetSentStatus", sender_account);
+
+    Ok(())
+  }
+
+  fn process_export_mailbox(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_owner(mail_account_info, program_id)?;
+
+    let scratch_account = &accounts[1];
+    Self::check_scratch(scratch_account, program_id)?;
+
+    let mail_account = Self::load_mail_account(mail_account_info)?;
+
+    Self::write_account(scratch_account, &mail_account)?;
+
+    Self::log_result("ExportMailbox", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_import_mailbox(
+    accounts: &[AccountInfo],
+    merge: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 3)?;
+
+    let backup_account = &accounts[0];
+    Self::check_owner(backup_account, program_id)?;
+    Self::check_initialized(backup_account, AccountNotInitialized)?;
+
+    let mail_account_info = &accounts[1];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[2];
+
+    // Decode the backup in full before touching the mailbox.
+    let backup = Self::load_mail_account(backup_account)?;
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if merge {
+      mail_account.merge_from(backup);
+    } else {
+      mail_account = MailAccount {
+        owner_pubkey: mail_account.owner_pubkey,
+        send_nonce: mail_account.send_nonce,
+        ..backup
+      };
+    }
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("ImportMailbox", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_transfer_mailbox(
+    accounts: &[AccountInfo],
+    new_owner: String,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    Pubkey::from_str(&new_owner).map_err(|_| InvalidAddress)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.owner_pubkey = new_owner;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("TransferMailbox", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_set_profile(
+    accounts: &[AccountInfo],
+    profile: Option<Profile>,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    if let Some(profile) = &profile {
+      profile.validate()?;
+    }
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.profile = profile;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetProfile", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_set_allowlist_mode(
+    accounts: &[AccountInfo],
+    enabled: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+    mail_account.allowlist_only = enabled;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetAllowlistMode", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_set_keep_sent(
+    accounts: &[AccountInfo],
+    enabled: bool,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.keep_sent = enabled;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetKeepSent", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_set_auto_archive(
+    accounts: &[AccountInfo],
+    days: Option<u32>,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.auto_archive_days = days;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetAutoArchive", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_set_retention(
+    accounts: &[AccountInfo],
+    max_age_secs: Option<u64>,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.max_age_secs = max_age_secs;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetRetention", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_auto_archive(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let mut mail_account = Self::load_mail_account(account)?;
+
+    let days = match mail_account.auto_archive_days {
+      Some(days) => days,
+      None => {
+        Self::log_result("AutoArchive", account);
+        return Ok(());
+      }
+    };
+
+    let now = Self::current_timestamp()?;
+    mail_account.archive_read_before(now.saturating_sub(u64::from(days) * 86_400));
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("AutoArchive", account);
+
+    Ok(())
+  }
+
+  fn process_set_read_cursor(
+    accounts: &[AccountInfo],
+    index: u32,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if usize::try_from(index).unwrap() >= mail_account.inbox.len() {
+      return Err(IndexOutOfBounds.into());
+    }
+
+    mail_account.last_read_index = index;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("SetReadCursor", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_move_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    from: Folder,
+    to: Folder,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::move_mail(accounts, mail_id, from, to, program_id)?;
+
+    Self::log_result("MoveMail", &accounts[0]);
+
+    Ok(())
+  }
+
+  fn process_delete_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::move_mail(accounts, mail_id, Folder::Inbox, Folder::Trash, program_id)?;
+
+    Self::log_result("DeleteMail", &accounts[0]);
+
+    Ok(())
+  }
+
+  fn process_restore_from_trash(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::move_mail(accounts, mail_id, Folder::Trash, Folder::Inbox, program_id)?;
+
+    Self::log_result("RestoreFromTrash", &accounts[0]);
+
+    Ok(())
+  }
+
+  /// Move a mail between folders of the owner-signed mailbox in `accounts`.
+  fn move_mail(
+    accounts: &[AccountInfo],
+    mail_id: &str,
+    from: Folder,
+    to: Folder,
+    program_id: &Pubkey,
+  ) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    mail_account.move_mail(mail_id, from, to)?;
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)
+  }
+
+  fn process_empty_trash(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    Self::check_accounts_len(accounts, 2)?;
+
+    let mail_account_info = &accounts[0];
+    Self::check_account(mail_account_info, program_id)?;
+
+    let owner_account = &accounts[1];
+
+    let mut mail_account = Self::load_mail_account(mail_account_info)?;
+    Self::check_owner_signer(owner_account, &mail_account)?;
+
+    if mail_account.trash.is_empty() {
+      Self::log_result("EmptyTrash", mail_account_info);
+      return Ok(());
+    }
+
+    mail_account.trash.clear();
+
+    Self::store_mail_account(mail_account_info, &mut mail_account)?;
+
+    Self::log_result("EmptyTrash", mail_account_info);
+
+    Ok(())
+  }
+
+  fn process_repair_header(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    if account.data_len() < MailboxHeader::LEN {
+      return Err(AccountTooSmall.into());
+    }
+
+    // Reading from the stream consumes only the bytes the mailbox needs, whatever the header says.
+    let mut mail_account =
+      MailAccount::deserialize(&mut &account.data.borrow()[MailboxHeader::LEN..])
+        .map_err(|_| InvalidAccountData)?;
+
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    Self::log_result("RepairHeader", account);
+
+    Ok(())
+  }
+
+  fn process_scrub_tail(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    Self::check_account(account, program_id)?;
+
+    let mut mail_account = Self::load_mail_account(account)?;
+    Self::store_mail_account(account, &mut mail_account)?;
+
+    let length = MailboxHeader::read(account)?.end()?;
+    account.data.borrow_mut()[length..].fill(0);
+
+    Self::log_result("ScrubTail", account);
+
+    Ok(())
+  }
+
+  /// The cluster's current unix timestamp, clamped to zero.
+  fn current_timestamp() -> Result<u64, ProgramError> {
+    Ok(u64::try_from(Clock::get()?.unix_timestamp).unwrap_or(0))
+  }
+
+  /// Ensure the account is writable and owned by this program.
+  fn check_account(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    if !account.is_writable {
+      return Err(NotWritable.into());
+    }
+
+    Self::check_owner(account, program_id)
+  }
+
+  /// Ensure a scratch account can take a query result. It must also sign, so a caller can only
+  /// overwrite an account they hold the key for and never a mailbox, whose address has none.
+  fn check_scratch(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    if !account.is_signer {
+      return Err(Unauthorized.into());
+    }
+
+    Self::check_account(account, program_id)
+  }
+
+  /// Ensure the mail is from the sending mailbox, so nobody can send in another's name.
+  fn check_sender(sender_account: &AccountInfo, mail: &Mail) -> ProgramResult {
+    if mail.from_address != sender_account.key.to_string() {
+      return Err(SenderMismatch.into());
+    }
+
+    Ok(())
+  }
+
+  /// Ensure `owner_account` signed and is the wallet recorded as the mailbox's owner.
+  fn check_owner_signer(owner_account: &AccountInfo, mail_account: &MailAccount) -> ProgramResult {
+    if !owner_account.is_signer || owner_account.key.to_string() != mail_account.owner_pubkey {
+      return Err(Unauthorized.into());
+    }
+
+    Ok(())
+  }
+
+  /// Ensure the account is owned by this program, for accounts that are only read.
+  /// An account still owned by the System Program was created but never assigned, and gets
+  /// its own error.
+  fn check_owner(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    if account.owner == program_id {
+      return Ok(());
+    }
+
+    if *account.owner == system_program::id() {
+      return Err(AccountNotAssigned.into());
+    }
+
+    Err(ProgramError::IncorrectProgramId)
+  }
+
+  /// Log a successful instruction as `OK <instruction> <account>`, where `<account>` is the
+  /// base58 key of the account acted on. Operators grep for this line, so keep it stable.
+  fn log_result(instruction: &str, account: &AccountInfo) {
+    msg!("OK {} {}", instruction, account.key);
+  }
+
+  /// Fail cleanly, rather than on an out-of-bounds index, when fewer than `expected` accounts
+  /// were passed.
+  fn check_accounts_len(accounts: &[AccountInfo], expected: usize) -> ProgramResult {
+    if accounts.len() < expected {
+      return Err(NotEnoughAccounts.into());
+    }
+
+    Ok(())
+  }
+
+  /// The account at `index`, for handlers that act on a single account.
+  fn account_at<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    index: usize,
+  ) -> Result<&'a AccountInfo<'b>, ProgramError> {
+    accounts.get(index).ok_or_else(|| NotEnoughAccounts.into())
+  }
+
+  /// Cap fan-out sends so a long recipient list fails cleanly instead of at the runtime limit.
+  fn check_recipient_count(count: usize) -> ProgramResult {
+    if count > MAX_RECIPIENTS {
+      return Err(TooManyRecipients.into());
+    }
+
+    Ok(())
+  }
+
+  /// Whether the account holds a stored mailbox, i.e. its length header is non-zero.
+  fn is_initialized(account: &AccountInfo) -> bool {
+    MailboxHeader::read(account)
+      .map(|header| header.is_initialized())
+      .unwrap_or(false)
+  }
+
+  /// Fail with `uninitialized` unless the account holds a stored mailbox.
+  fn check_initialized(account: &AccountInfo, uninitialized: MailError) -> ProgramResult {
+    if account.data_len() < MailboxHeader::LEN {
+      return Err(AccountTooSmall.into());
+    }
+
+    if !Self::is_initialized(account) {
+      return Err(uninitialized.into());
+    }
+
+    Ok(())
+  }
+
+  /// Ensure an Ed25519 program instruction in the same transaction checked `mail.signature`
+  /// by `owner_pubkey` over the mail's signing message. The signature program fails the
+  /// whole transaction on a bad signature, so finding a matching check is enough.
+  fn verify_signature(
+    mail: &Mail,
+    owner_pubkey: &str,
+    instructions_account: &AccountInfo,
+  ) -> ProgramResult {
+    if *instructions_account.key != instructions::id() {
+      return Err(ProgramError::InvalidArgument);
+    }
+
+    let signer = Pubkey::from_str(owner_pubkey).map_err(|_| InvalidSignature)?;
+    let message = mail.signing_message();
+    let data = instructions_account.data.borrow();
+
+    let mut index = 0;
+    while let Ok(instruction) = instructions::load_instruction_at(index, &data) {
+      if instruction.program_id == ed25519_program::id()
+        && Self::ed25519_checks(
+          &instruction.data,
+          u16::try_from(index).unwrap_or(u16::MAX),
+          &signer,
+          &mail.signature,
+          &message,
+        )
+      {
+        return Ok(());
+      }
+      index += 1;
+    }
+
+    Err(InvalidSignature.into())
+  }
+
+  /// Whether the Ed25519 program instruction data, found at `own_index`, checks `signature`
+  /// by `signer` over `message`. Only offsets into the instruction's own data are accepted,
+  /// since those are the bytes read here.
+  fn ed25519_checks(
+    data: &[u8],
+    own_index: u16,
+    signer: &Pubkey,
+    signature: &[u8],
+    message: &[u8],
+  ) -> bool {
+    let read_u16 = |at: usize| {
+      data
+        .get(at..at + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let slice = |offset: Option<u16>, len: usize| {
+      offset.and_then(|offset| data.get(usize::from(offset)..usize::from(offset) + len))
+    };
+
+    let count = data.first().copied().unwrap_or(0);
+
+    (0..usize::from(count)).any(|i| {
+      let at = ED25519_OFFSETS_START + i * ED25519_OFFSETS_LEN;
+      let own_data = |index: Option<u16>| matches!(index, Some(index) if index == own_index || index == u16::MAX);
+
+      own_data(read_u16(at + 2))
+        && own_data(read_u16(at + 6))
+        && own_data(read_u16(at + 12))
+        && slice(read_u16(at), ED25519_SIGNATURE_LEN) == Some(signature)
+        && slice(read_u16(at + 4), 32) == Some(signer.as_ref())
+        && read_u16(at + 10).map(usize::from) == Some(message.len())
+        && slice(read_u16(at + 8), message.len()) == Some(message)
+    })
+  }
+
+  /// Read the [MailAccount](../state/struct.MailAccount.html) stored after the length header.
+  /// An account whose header is still zero yields an empty mailbox.
+  fn load_mail_account(account: &AccountInfo) -> Result<MailAccount, ProgramError> {
+    Self::load_with_header(account)
+  }
+
+  /// Read a value stored in the mailbox layout: a length header followed by its serialized
+  /// form. An account whose header is still zero yields the default value.
+  fn load_with_header<T: BorshDeserialize + Default>(
+    account: &AccountInfo,
+  ) -> Result<T, ProgramError> {
+    let header = MailboxHeader::read(account)?;
+
+    if !header.is_initialized() {
+      return Ok(T::default());
+    }
+
+    let length = header.end()?;
+
+    if length > account.data_len() {
+      return Err(ProgramError::InvalidAccountData);
+    }
+
+    let value = T::try_from_slice(&account.data.borrow()[MailboxHeader::LEN..length])?;
+
+    Ok(value)
+  }
+
+  /// Ensure the header and serialized value fit in the account.
+  fn ensure_fits<T: BorshSerialize>(account: &AccountInfo, value: &T) -> ProgramResult {
+    let length = MailboxHeader::LEN
+      .checked_add(Self::packed_len(account, value)?)
+      .ok_or(ArithmeticOverflow)?;
+
+    if length > account.data_len() {
+      return Err(AccountTooSmall.into());
+    }
+
+    Ok(())
+  }
+
+  /// Bytes left in the account once the mailbox is stored. Growth already applied to its
+  /// folders in memory is included, so every push is sized against the whole mailbox.
+  fn free_bytes(account: &AccountInfo, mail_account: &MailAccount) -> Result<usize, ProgramError> {
+    let length = MailboxHeader::LEN
+      .checked_add(Self::mailbox_packed_len(account, mail_account)?)
+      .ok_or(ArithmeticOverflow)?;
+
+    Ok(account.data_len().saturating_sub(length))
+  }
+
+  /// Write the length header followed by the serialized [MailAccount](../state/struct.MailAccount.html),
+  /// refreshing `bytes_used` to the packed length. Replies whose parent is gone are unlinked
+  /// and the read cursor is clamped to the inbox first. With the `strict` feature the
+  /// mailbox must then pass `MailAccount::validate`.
+  fn store_mail_account(account: &AccountInfo, mail_account: &mut MailAccount) -> ProgramResult {
+    mail_account.unlink_dangling_replies();
+    mail_account.clamp_read_cursor();
+
+    #[cfg(feature = "strict")]
+    mail_account.validate()?;
+
+    mail_account.bytes_used =
+      u32::try_from(Self::mailbox_packed_len(account, mail_account)?).unwrap();
+
+    Self::write_account(account, mail_account)
+  }
+
+  /// The packed length of a value bound for `account`, logging the account when the value
+  /// cannot be serialized.
+  fn packed_len<T: BorshSerialize>(
+    account: &AccountInfo,
+    value: &T,
+  ) -> Result<usize, ProgramError> {
+    get_instance_packed_len(value).map_err(|_| {
+      msg!("SerializationFailed: {}", account.key);
+      SerializationFailed.into()
+    })
+  }
+
+  /// Like [packed_len](#method.packed_len), but also logs the size of every folder, which is
+  /// usually where an oversized mailbox went wrong.
+  fn mailbox_packed_len(
+    account: &AccountInfo,
+    mail_account: &MailAccount,
+  ) -> Result<usize, ProgramError> {
+    get_instance_packed_len(mail_account).map_err(|_| {
+      msg!(
+        "SerializationFailed: {} inbox={} sent={} archive={} drafts={} trash={}",
+        account.key,
+        mail_account.inbox.len(),
+        mail_account.sent.len(),
+        mail_account.archive.len(),
+        mail_account.drafts.len(),
+        mail_account.trash.len()
+      );
+      SerializationFailed.into()
+    })
+  }
+
+  /// Write the length header followed by the serialized value, in the layout read by
+  /// [load_with_header](#method.load_with_header). Mailboxes and query results written into
+  /// scratch accounts share it. Nothing is written unless the whole value serializes and
+  /// fits, and the header goes last, so it never describes a body that was not written.
+  fn write_account<T: BorshSerialize>(account: &AccountInfo, value: &T) -> ProgramResult {
+    let body = value.try_to_vec().map_err(|_| {
+      msg!("SerializationFailed: {}", account.key);
+      SerializationFailed
+    })?;
+    let length = MailboxHeader::LEN
+      .checked_add(body.len())
+      .ok_or(ArithmeticOverflow)?;
+
+    if length > account.data_len() {
+      return Err(AccountTooSmall.into());
+    }
+
+    account.data.borrow_mut()[MailboxHeader::LEN..length].copy_from_slice(&body);
+    MailboxHeader::write(account, body.len())?;
+
+    Ok(())
+  }
+}
+
+/// Mailboxes touched by a single instruction. An account passed more than once, such as
+/// when mailing yourself, is loaded once so every change to it lands in a single write.
+#[derive(Default)]
+struct Mailboxes<'a, 'b> {
+  entries: Vec<(&'a AccountInfo<'b>, MailAccount)>,
+}
+
+impl<'a, 'b> Mailboxes<'a, 'b> {
+  /// The in-memory mailbox for the account, loading it on first use.
+  fn get_mut(&mut self, account: &'a AccountInfo<'b>) -> Result<&mut MailAccount, ProgramError> {
+    let index = match self
+      .entries
+      .iter()
+      .position(|(loaded, _)| Rc::ptr_eq(&loaded.data, &account.data))
+    {
+      Some(index) => index,
+      None => {
+        let mail_account = Processor::load_mail_account(account)?;
+        self.entries.push((account, mail_account));
+        self.entries.len() - 1
+      }
+    };
+
+    Ok(&mut self.entries[index].1)
+  }
+
+  /// Append a copy of a sent mail to the sender's sent folder, unless the sender turned
+  /// `keep_sent` off. The sender must be an initialized mailbox either way.
+  fn record_sent(&mut self, sender_account: &'a AccountInfo<'b>, mut mail: Mail) -> ProgramResult {
+    Processor::check_initialized(sender_account, AccountNotInitialized)?;
+    Processor::check_sender(sender_account, &mail)?;
+    mail.stamp_byte_size();
+    let sender = self.get_mut(sender_account)?;
+    if !sender.keep_sent {
+      return Ok(());
+    }
+    sender.check_new_id(Folder::Sent, &mail.id)?;
+    Self::charge(sender, &mail)?;
+    Self::ensure_room(sender_account, sender, &mail)?;
+    sender.sent.push(mail);
+
+    Ok(())
+  }
+
+  /// Ensure the receiver can take the mail from the sender, before anything is appended for it.
+  fn admit(
+    &mut self,
+    sender_account: &AccountInfo,
+    receiver_account: &'a AccountInfo<'b>,
+  ) -> ProgramResult {
+    Processor::check_initialized(receiver_account, ReceiverNotInitialized)?;
+
+    if !self
+      .get_mut(receiver_account)?
+      .accepts_from(&sender_account.key.to_string())
+    {
+      return Err(SenderNotAllowlisted.into());
+    }
+
+    Ok(())
+  }
+
+  /// Append a copy of a sent mail to the receiver's inbox, stamped with the time it arrived.
+  /// A mail sent at priority `0` gets the priority `classify` assigns from the receiver's
+  /// contacts. Inbox mail older than the receiver's `max_age_secs` is pruned first.
+  fn deliver(
+    &mut self,
+    sender_account: &AccountInfo,
+    receiver_account: &'a AccountInfo<'b>,
+    mut mail: Mail,
+  ) -> ProgramResult {
+    self.admit(sender_account, receiver_account)?;
+    let now = Processor::current_timestamp()?;
+    mail.delivered_at = now;
+    let receiver = self.get_mut(receiver_account)?;
+    if mail.priority == 0 {
+      mail.priority = classify(&mail, &receiver.contacts);
+    }
+    mail.stamp_byte_size();
+    if let Some(max_age_secs) = receiver.max_age_secs {
+      receiver.prune_inbox_before(now.saturating_sub(max_age_secs));
+    }
+    receiver.check_new_id(Folder::Inbox, &mail.id)?;
+    Self::charge(receiver, &mail)?;
+    Self::ensure_room(receiver_account, receiver, &mail)?;
+    receiver.inbox.push(mail);
+
+    Ok(())
+  }
+
+  /// Ensure `deliver` would accept the mail, without changing any mailbox. Retention pruning
+  /// is not counted, so a mail that only fits after it is reported as not deliverable.
+  fn check_deliverable(
+    &mut self,
+    sender_account: &AccountInfo,
+    receiver_account: &'a AccountInfo<'b>,
+    mail: &Mail,
+  ) -> ProgramResult {
+    self.admit(sender_account, receiver_account)?;
+    let mut mail = mail.clone();
+    mail.stamp_byte_size();
+    let receiver = self.get_mut(receiver_account)?;
+    receiver.check_new_id(Folder::Inbox, &mail.id)?;
+    Self::check_quota(receiver, &mail)?;
+    Self::ensure_room(receiver_account, receiver, &mail)
+  }
+
+  /// Count a stamped mail against the mailbox quota. The exact figure is refreshed on store.
+  fn charge(mail_account: &mut MailAccount, mail: &Mail) -> ProgramResult {
+    mail_account.bytes_used = Self::check_quota(mail_account, mail)?;
+
+    Ok(())
+  }
+
+  /// The quota figure once a stamped mail is counted, failing when it exceeds the quota.
+  fn check_quota(mail_account: &MailAccount, mail: &Mail) -> Result<u32, ProgramError> {
+    let bytes_used = mail_account.bytes_used.saturating_add(mail.byte_size);
+
+    if bytes_used > MAX_ACCOUNT_BYTES {
+      return Err(QuotaExceeded.into());
+    }
+
+    Ok(bytes_used)
+  }
+
+  /// Fail early when appending a stamped mail would outgrow the account, logging the bytes
+  /// the mail needs against those still free.
+  fn ensure_room(account: &AccountInfo, mail_account: &MailAccount, mail: &Mail) -> ProgramResult {
+    let needed = usize::try_from(mail.byte_size).unwrap();
+    let available = Processor::free_bytes(account, mail_account)?;
+
+    if needed > available {
+      msg!(
+        "AccountTooSmall: {} needs {} bytes, {} available",
+        account.key,
+        needed,
+        available
+      );
+      return Err(AccountTooSmall.into());
+    }
+
+    Ok(())
+  }
+
+  /// Ensure every mailbox fits its account.
+  fn check(&self) -> ProgramResult {
+    for (account, mail_account) in &self.entries {
+      Processor::ensure_fits(account, mail_account)?;
+    }
+
+    Ok(())
+  }
+
+  /// Size every mailbox before writing any, so one that does not fit leaves all accounts untouched.
+  fn commit(mut self) -> ProgramResult {
+    self.check()?;
+
+    for (account, mail_account) in &mut self.entries {
+      Processor::store_mail_account(account, mail_account)?;
+    }
+
+    Ok(())
+  }
+}
+
+// Sanity tests
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::error::MailError::{
+    AvatarUriTooLong, DisplayNameTooLong, DuplicateMailId, EmptyMessage, InvalidPriority,
+    SubjectTooLong, TooManyReactions,
+  };
+  use crate::limits::{
+    MAX_AVATAR_URI_LEN, MAX_DISPLAY_NAME_LEN, MAX_PRIORITY, MAX_REACTIONS, MAX_RECENT_KEYS,
+    MAX_SUBJECT_LEN,
+  };
+  use crate::state::{DataLength, LegacyMail, MailStats, Page};
+  use solana_program::{
+    clock::Epoch, entrypoint::SUCCESS, instruction::Instruction, program_stubs,
+  };
+  use std::cell::RefCell;
+  use std::sync::Once;
+
+  /// Timestamp reported by the stubbed `Clock` sysvar.
+  const NOW: u64 = 1_633_000_000;
+
+  thread_local! {
+    /// Messages logged by the current test's thread.
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// Cross-program invocations made by the current test's thread.
+    static INVOKES: RefCell<Vec<Invoke>> = const { RefCell::new(Vec::new()) };
+  }
+
+  /// A recorded cross-program invocation: the instruction, the keys of the accounts passed
+  /// with it and the seeds it was signed with.
+  type Invoke = (Instruction, Vec<Pubkey>, Vec<Vec<Vec<u8>>>);
+
+  struct TestSyscallStubs;
+  impl program_stubs::SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, message: &str) {
+      LOGS.with(|logs| logs.borrow_mut().push(String::from(message)));
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+      let clock = Clock {
+        unix_timestamp: NOW as i64,
+        ..Clock::default()
+      };
+      unsafe { *(var_addr as *mut Clock) = clock };
+      SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+      unsafe { *(var_addr as *mut Rent) = Rent::default() };
+      SUCCESS
+    }
+
+    fn sol_invoke_signed(
+      &self,
+      instruction: &Instruction,
+      account_infos: &[AccountInfo],
+      signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+      let invoke = (
+        instruction.clone(),
+        account_infos.iter().map(|account| *account.key).collect(),
+        signers_seeds
+          .iter()
+          .map(|seeds| seeds.iter().map(|seed| seed.to_vec()).collect())
+          .collect(),
+      );
+      INVOKES.with(|invokes| invokes.borrow_mut().push(invoke));
+      Ok(())
+    }
+  }
+
+  /// The mail as the program stores it once sent.
+  fn stamped(mut mail: Mail) -> Mail {
+    mail.stamp_byte_size();
+    mail
+  }
+
+  /// The receiver's copy of a mail delivered by `SendMail` at the stubbed time.
+  fn delivered(mail: Mail) -> Mail {
+    stamped(Mail {
+      delivered_at: NOW,
+      ..mail
+    })
+  }
+
+  /// An empty mailbox owned by the wallet `owner`.
+  fn owned_by(owner: &Pubkey) -> MailAccount {
+    MailAccount {
+      owner_pubkey: owner.to_string(),
+      ..MailAccount::default()
+    }
+  }
+
+  fn stub_clock() {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+      program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs));
+    });
+  }
+
+  #[test]
+  fn test_init_account() {
+    let program_id = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let (key, _) = find_mailbox_address(&owner_key, &program_id);
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let system_key = system_program::id();
+    let mut system_lamports = 0;
+    let mut system_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let system_account = AccountInfo::new(
+      &system_key,
+      false,
+      false,
+      &mut system_lamports,
+      &mut system_data,
+      &system_key,
+      true,
+      Epoch::default(),
+    );
+
+    let accounts = vec![
+      owner_account.clone(),
+      owner_account.clone(),
+      system_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(NotWritable.into())
+    );
+
+    let accounts = vec![account.clone(), account.clone(), system_account.clone()];
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(InvalidMailboxAddress.into())
+    );
+    assert_eq!(*account.data.borrow(), &[0; 1000][..]);
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let accounts = vec![account.clone(), unsigned_owner, system_account.clone()];
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(Unauthorized.into())
+    );
+
+    let accounts = vec![
+      account.clone(),
+      owner_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(ProgramError::IncorrectProgramId)
+    );
+
+    let accounts = vec![account.clone(), owner_account.clone(), system_account];
+    Processor::process_init_account(&accounts, None, &program_id).unwrap();
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(AlreadyInitialized.into())
+    );
+
+    let data_length = DataLength::try_from_slice(&account.data.borrow()[..4]).unwrap();
+    let mail_account = MailAccount::try_from_slice(
+      &account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
+    )
+    .unwrap();
+
+    assert!(mail_account.inbox.is_empty());
+    assert_eq!(mail_account.owner_pubkey, owner_key.to_string());
+  }
+
+  #[test]
+  fn test_init_account_creates_mailbox() {
+    stub_clock();
+    let program_id = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let (key, bump) = find_mailbox_address(&owner_key, &program_id);
+    let system_key = system_program::id();
+    let rent = Rent::default().minimum_balance(MAILBOX_LEN);
+    let space = MAILBOX_LEN as u64;
+    let seeds = vec![vec![
+      MAILBOX_SEED.to_vec(),
+      owner_key.as_ref().to_vec(),
+      vec![bump],
+    ]];
+
+    for (funded, expected) in vec![
+      (
+        0,
+        vec![(
+          system_instruction::create_account(&owner_key, &key, rent, space, &program_id),
+          vec![owner_key, key, system_key],
+          seeds.clone(),
+        )],
+      ),
+      (
+        1,
+        vec![
+          (
+            system_instruction::transfer(&owner_key, &key, rent - 1),
+            vec![owner_key, key, system_key],
+            vec![],
+          ),
+          (
+            system_instruction::allocate(&key, space),
+            vec![key, system_key],
+            seeds.clone(),
+          ),
+          (
+            system_instruction::assign(&key, &program_id),
+            vec![key, system_key],
+            seeds.clone(),
+          ),
+        ],
+      ),
+    ] {
+      let mut lamports = funded;
+      let mut data = vec![0; MAILBOX_LEN];
+      let mut owner_lamports = rent;
+      let mut owner_data = [0; 0];
+      let mut system_lamports = 0;
+      let mut system_data = [0; 0];
+
+      let account = AccountInfo::new(
+        &key,
+        false,
+        true,
+        &mut lamports,
+        &mut data,
+        &system_key,
+        false,
+        Epoch::default(),
+      );
+      let owner_account = AccountInfo::new(
+        &owner_key,
+        true,
+        true,
+        &mut owner_lamports,
+        &mut owner_data,
+        &system_key,
+        false,
+        Epoch::default(),
+      );
+      let system_account = AccountInfo::new(
+        &system_key,
+        false,
+        false,
+        &mut system_lamports,
+        &mut system_data,
+        &system_key,
+        true,
+        Epoch::default(),
+      );
+
+      INVOKES.with(|invokes| invokes.borrow_mut().clear());
+      let accounts = vec![account.clone(), owner_account, system_account];
+      Processor::process_init_account(&accounts, None, &program_id).unwrap();
+
+      assert_eq!(INVOKES.with(|invokes| invokes.borrow().clone()), expected);
+      let mail_account = Processor::load_mail_account(&account).unwrap();
+      assert_eq!(mail_account.owner_pubkey, owner_key.to_string());
+    }
+  }
+
+  #[test]
+  fn test_init_account_with_welcome() {
+    let program_id = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let (key, _) = find_mailbox_address(&owner_key, &program_id);
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let system_key = system_program::id();
+    let mut system_lamports = 0;
+    let mut system_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let system_account = AccountInfo::new(
+      &system_key,
+      false,
+      false,
+      &mut system_lamports,
+      &mut system_data,
+      &system_key,
+      true,
+      Epoch::default(),
+    );
+
+    let accounts = vec![account.clone(), owner_account, system_account];
+    Processor::process_init_account(&accounts, Some((None, None)), &program_id).unwrap();
+
+    let inbox = Processor::load_mail_account(&account).unwrap().inbox;
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].subject, "Welcome to SolMail");
+    assert_eq!(inbox[0].to_address, key.to_string());
+
+    let welcome = Some((
+      Some(String::from("Hello team")),
+      Some(String::from("Glad you're here")),
+    ));
+    assert_eq!(
+      Processor::process_init_account(&accounts, welcome.clone(), &program_id),
+      Err(AlreadyInitialized.into())
+    );
+    let inbox = Processor::load_mail_account(&account).unwrap().inbox;
+    assert_eq!(inbox[0].subject, "Welcome to SolMail");
+
+    account
+      .data
+      .borrow_mut()
+      .iter_mut()
+      .for_each(|byte| *byte = 0);
+    Processor::process_init_account(&accounts, welcome, &program_id).unwrap();
+
+    let inbox = Processor::load_mail_account(&account).unwrap().inbox;
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].subject, "Hello team");
+    assert_eq!(inbox[0].body, "Glad you're here");
+  }
+
+  #[test]
+  fn test_send_mail() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut sender_data = [0; 2000];
+
+    let sender_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let mut lamports = 0;
+
+    let receiver_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: sender_account.key.to_string(),
+      to_address: receiver_account.key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      body: String::from(
+        "Lorem, ipsum dolor sit amet consectetur adipisicing elit. Quos ut labore, debitis assumenda, dolorem nulla facere soluta exercitationem excepturi provident ipsam reprehenderit repellat quisquam corrupti commodi fugiat iusto quae voluptates!"
+      ),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for unauthorized in vec![
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        unsigned_owner,
+      ],
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        receiver_account.clone(),
+      ],
+    ] {
+      assert_eq!(
+        Processor::process_send_mail(&unauthorized, &mail, false, 0, false, false, &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+
+    let data_length = DataLength::try_from_slice(&sender_account.data.borrow()[..4]).unwrap();
+    let mail_account = MailAccount::try_from_slice(
+      &sender_account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
+    )
+    .unwrap();
+
+    assert_eq!(mail_account.sent[0].subject, "Hey Mike!!!");
+
+    let data_length = DataLength::try_from_slice(&receiver_account.data.borrow()[..4]).unwrap();
+    let mail_account = MailAccount::try_from_slice(
+      &receiver_account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
+    )
+    .unwrap();
+
+    assert_eq!(mail_account.inbox[0].subject, "Hey Mike!!!");
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 1, false, false, &program_id),
+      Err(DuplicateMailId.into())
+    );
+  }
+
+  #[test]
+  fn test_update_draft() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let draft = |id: &str, subject: &str| Mail {
+      id: String::from(id),
+      from_address: key.to_string(),
+      to_address: key.to_string(),
+      subject: String::from(subject),
+      body: String::from("Draft body"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      drafts: vec![
+        draft("1", "First"),
+        draft("2", "Second"),
+        draft("3", "Third"),
+      ],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let edited = draft("2", "Second, edited");
+    Processor::process_update_draft(&accounts, "2", &edited, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+
+    assert_eq!(mail_account.drafts.len(), 3);
+    assert_eq!(mail_account.drafts[0].subject, "First");
+    assert_eq!(mail_account.drafts[1], edited);
+    assert_eq!(mail_account.drafts[2].subject, "Third");
+
+    let missing = draft("4", "Missing");
+    assert_eq!(
+      Processor::process_update_draft(&accounts, "4", &missing, &program_id),
+      Err(MailNotFound.into())
+    );
+
+    let emptied = Mail {
+      subject: String::new(),
+      body: String::new(),
+      ..edited.clone()
+    };
+    assert_eq!(
+      Processor::process_update_draft(&accounts, "2", &emptied, &program_id),
+      Err(EmptyMessage.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().drafts[1],
+      edited
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let unsigned = vec![account.clone(), unsigned_owner];
+    assert_eq!(
+      Processor::process_update_draft(&unsigned, "2", &edited, &program_id),
+      Err(Unauthorized.into())
+    );
+  }
+
+  #[test]
+  fn test_edit_sent_subject() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, subject: &str| Mail {
+      id: String::from(id),
+      subject: String::from(subject),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1", "Hey Mkie")],
+      sent: vec![mail("1", "Hey Mkie"), mail("2", "Lunch?")],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    Processor::process_edit_sent_subject(&accounts, "1", String::from("Hey Mike"), &program_id)
+      .unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(
+      mail_account.sent,
+      vec![stamped(mail("1", "Hey Mike")), mail("2", "Lunch?")]
+    );
+    assert_eq!(mail_account.inbox, vec![mail("1", "Hey Mkie")]);
+
+    assert_eq!(
+      Processor::process_edit_sent_subject(&accounts, "3", String::from("Hey"), &program_id),
+      Err(MailNotFound.into())
+    );
+    assert_eq!(
+      Processor::process_edit_sent_subject(
+        &accounts,
+        "2",
+        "a".repeat(MAX_SUBJECT_LEN + 1),
+        &program_id
+      ),
+      Err(SubjectTooLong.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_edit_sent_subject(
+        &[account.clone(), unsigned_owner],
+        "2",
+        String::from("Dinner?"),
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+  }
+
+  #[test]
+  fn test_update_draft_id_mismatch() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let draft = Mail {
+      id: String::from("1"),
+      from_address: key.to_string(),
+      to_address: key.to_string(),
+      subject: String::from("First"),
+      body: String::from("Draft body"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      drafts: vec![draft.clone()],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let edited = Mail {
+      id: String::from("2"),
+      ..draft.clone()
+    };
+
+    assert_eq!(
+      Processor::process_update_draft(&accounts, "1", &edited, &program_id),
+      Err(MailIdMismatch.into())
+    );
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.drafts, vec![draft]);
+  }
+
+  #[test]
+  fn test_save_draft() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&account, &mut owned_by(&owner_key)).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let draft = |id: &str, subject: &str| Mail {
+      id: String::from(id),
+      subject: String::from(subject),
+      ..Mail::default()
+    };
+
+    Processor::process_save_draft(&accounts, draft("1", "First"), &program_id).unwrap();
+    Processor::process_save_draft(&accounts, draft("2", "Second"), &program_id).unwrap();
+
+    assert_eq!(
+      Processor::process_save_draft(&accounts, draft("1", "Again"), &program_id),
+      Err(DuplicateDraft.into())
+    );
+    assert_eq!(
+      Processor::process_save_draft(&accounts, draft("3", ""), &program_id),
+      Err(EmptyMessage.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_save_draft(
+        &[account.clone(), unsigned_owner],
+        draft("3", "Third"),
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().drafts,
+      vec![draft("1", "First"), draft("2", "Second")]
+    );
+
+    let edited = draft("2", "Second, edited");
+    Processor::process_update_draft(&accounts, "2", &edited, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().drafts,
+      vec![draft("1", "First"), edited]
+    );
+  }
+
+  #[test]
+  fn test_delete_all_read() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      read,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![
+        mail("1", true),
+        mail("2", false),
+        mail("3", true),
+        mail("4", false),
+      ],
+      sent: vec![mail("5", true)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_delete_all_read(&[account.clone(), unsigned_owner], &program_id),
+      Err(Unauthorized.into())
+    );
+
+    Processor::process_delete_all_read(&accounts, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox, vec![mail("2", false), mail("4", false)]);
+    assert_eq!(mail_account.sent, vec![mail("5", true)]);
+
+    Processor::process_delete_all_read(&accounts, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox.len(), 2);
+  }
+
+  #[test]
+  fn test_mark_all_read() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      read,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1", true), mail("2", false), mail("3", false)],
+      sent: vec![mail("4", false)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_mark_all_read(&[account.clone(), unsigned_owner], &program_id),
+      Err(Unauthorized.into())
+    );
+
+    Processor::process_mark_all_read(&accounts, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(
+      mail_account.inbox,
+      vec![mail("1", true), mail("2", true), mail("3", true)]
+    );
+    assert_eq!(mail_account.sent, vec![mail("4", false)]);
+    assert_eq!(mail_account.last_read_index, 2);
+
+    let before = account.data.borrow().to_vec();
+    Processor::process_mark_all_read(&accounts, &program_id).unwrap();
+    assert_eq!(*account.data.borrow(), &before[..]);
+  }
+
+  #[test]
+  fn test_send_mail_invalid_priority() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: key.to_string(),
+      to_address: key.to_string(),
+      subject: String::from("Urgent"),
+      priority: MAX_PRIORITY + 1,
+      ..Mail::default()
+    };
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(InvalidPriority.into())
+    );
+    assert!(Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox
+      .is_empty());
+
+    let mail = Mail {
+      priority: MAX_PRIORITY,
+      ..mail
+    };
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox,
+      vec![delivered(mail)]
+    );
+  }
+
+  #[test]
+  fn test_purge_expired() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, expires_at: Option<u64>| Mail {
+      id: String::from(id),
+      expires_at,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![
+        mail("expired", Some(NOW - 1)),
+        mail("future", Some(NOW + 60)),
+        mail("forever", None),
+      ],
+      sent: vec![mail("expires-now", Some(NOW)), mail("sent-forever", None)],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+
+    Processor::process_purge_expired(&account, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(
+      mail_account.inbox,
+      vec![mail("future", Some(NOW + 60)), mail("forever", None)]
+    );
+    assert_eq!(mail_account.sent, vec![mail("sent-forever", None)]);
+  }
+
+  #[test]
+  fn test_send_mail_receiver_too_small() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 120];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut seeded = MailAccount {
+      sent: vec![Mail {
+        id: String::from("1"),
+        ..Mail::default()
+      }],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut seeded).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let sender_before = sender_account.data.borrow().to_vec();
+    let receiver_before = receiver_account.data.borrow().to_vec();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = Mail {
+      id: String::from("2"),
+      from_address: key.to_string(),
+      to_address: key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      body: String::from("A body that will not fit in the receiver account"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(AccountTooSmall.into())
+    );
+    assert_eq!(*sender_account.data.borrow(), &sender_before[..]);
+    assert_eq!(*receiver_account.data.borrow(), &receiver_before[..]);
+  }
+
+  #[test]
+  fn test_contacts() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(
+      &account,
+      &mut MailAccount {
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let mike = Pubkey::new_unique().to_string();
+    let jane = Pubkey::new_unique().to_string();
+
+    Processor::process_add_contact(&accounts, String::from("mike"), mike.clone(), &program_id)
+      .unwrap();
+    Processor::process_add_contact(&accounts, String::from("jane"), jane.clone(), &program_id)
+      .unwrap();
+
+    assert_eq!(
+      Processor::process_add_contact(&accounts, String::from("mike"), jane.clone(), &program_id),
+      Err(DuplicateContact.into())
+    );
+    assert_eq!(
+      Processor::process_add_contact(
+        &accounts,
+        String::from("bob"),
+        String::from("not-a-pubkey"),
+        &program_id
+      ),
+      Err(InvalidAddress.into())
+    );
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(
+      mail_account.contacts,
+      vec![
+        (String::from("mike"), mike.clone()),
+        (String::from("jane"), jane.clone())
+      ]
+    );
+
+    Processor::process_remove_contact(&accounts, "mike", &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.contacts, vec![(String::from("jane"), jane)]);
+
+    assert_eq!(
+      Processor::process_remove_contact(&accounts, "mike", &program_id),
+      Err(ContactNotFound.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let unsigned = vec![account.clone(), unsigned_owner];
+    assert_eq!(
+      Processor::process_add_contact(&unsigned, String::from("mike"), mike.clone(), &program_id),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(
+      Processor::process_remove_contact(&unsigned, "jane", &program_id),
+      Err(Unauthorized.into())
+    );
+  }
+
+  #[test]
+  fn test_get_stats() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 64];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      read,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1", false), mail("2", true), mail("3", false)],
+      sent: vec![mail("4", true)],
+      archive: vec![mail("5", true), mail("6", true)],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+    Processor::process_get_stats(&accounts, &program_id).unwrap();
+
+    let data_length = DataLength::try_from_slice(&scratch_account.data.borrow()[..4]).unwrap();
+    let stats = MailStats::try_from_slice(
+      &scratch_account.data.borrow()[4..usize::try_from(data_length.length + 4).unwrap()],
+    )
+    .unwrap();
+
+    assert_eq!(
+      stats,
+      MailStats {
+        total_inbox: 3,
+        unread: 2,
+        sent: 1,
+        archived: 2,
+        storage_bytes: 0,
+      }
+    );
+  }
+
+  #[test]
+  fn test_send_mail_bcc() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let first_key = Pubkey::new_unique();
+    let second_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut first_lamports = 0;
+    let mut first_data = [0; 1000];
+    let mut second_lamports = 0;
+    let mut second_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let first_account = AccountInfo::new(
+      &first_key,
+      false,
+      true,
+      &mut first_lamports,
+      &mut first_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let second_account = AccountInfo::new(
+      &second_key,
+      false,
+      true,
+      &mut second_lamports,
+      &mut second_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      first_account.clone(),
+      second_account.clone(),
+    ];
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      subject: String::from("Urgent: hey everyone"),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    assert_eq!(
+      Processor::process_send_mail_bcc(&accounts, &mail, &program_id),
+      Err(ReceiverNotInitialized.into())
+    );
+
+    for account in &accounts[2..] {
+      Processor::store_mail_account(account, &mut MailAccount::default()).unwrap();
+    }
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for unauthorized in vec![
+      vec![
+        sender_account.clone(),
+        unsigned_owner,
+        first_account.clone(),
+        second_account.clone(),
+      ],
+      vec![
+        sender_account.clone(),
+        first_account.clone(),
+        second_account.clone(),
+      ],
+    ] {
+      assert_eq!(
+        Processor::process_send_mail_bcc(&unauthorized, &mail, &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+    Processor::process_send_mail_bcc(&accounts, &mail, &program_id).unwrap();
+
+    for (account, key) in [(&first_account, first_key), (&second_account, second_key)] {
+      let inbox = Processor::load_mail_account(account).unwrap().inbox;
+      assert_eq!(inbox.len(), 1);
+      assert_eq!(inbox[0].to_address, key.to_string());
+      assert!(inbox[0].bcc.is_empty());
+      assert_eq!(inbox[0].priority, 1);
+    }
+
+    let sent = Processor::load_mail_account(&sender_account).unwrap().sent;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].priority, 0);
+    assert_eq!(
+      sent[0].bcc,
+      vec![first_key.to_string(), second_key.to_string()]
+    );
+  }
+
+  #[test]
+  fn test_send_mail_bcc_too_many_recipients() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let keys: Vec<Pubkey> = (0..=MAX_RECIPIENTS + 1)
+      .map(|_| Pubkey::new_unique())
+      .collect();
+    let mut lamports = vec![0; keys.len()];
+    let mut data = vec![[0; 2000]; keys.len()];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut accounts: Vec<AccountInfo> = keys
+      .iter()
+      .zip(lamports.iter_mut())
+      .zip(data.iter_mut())
+      .map(|((key, lamports), data)| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          data,
+          &program_id,
+          false,
+          Epoch::default(),
+        )
+      })
+      .collect();
+    accounts.insert(1, owner_account);
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: keys[0].to_string(),
+      subject: String::from("Hey everyone"),
+      ..Mail::default()
+    };
+
+    assert_eq!(
+      Processor::process_send_mail_bcc(&accounts, &mail, &program_id),
+      Err(TooManyRecipients.into())
+    );
+    assert!(accounts
+      .iter()
+      .all(|account| account.data.borrow().iter().all(|byte| *byte == 0)));
+
+    Processor::store_mail_account(&accounts[0], &mut owned_by(&owner_key)).unwrap();
+    for account in &accounts[2..=MAX_RECIPIENTS + 1] {
+      Processor::store_mail_account(account, &mut MailAccount::default()).unwrap();
+    }
+    Processor::process_send_mail_bcc(&accounts[..=MAX_RECIPIENTS + 1], &mail, &program_id).unwrap();
+  }
+  #[test]
+  fn test_labels() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 2000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: vec![Mail {
+        id: String::from("1"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+
+    let labels = |account: &AccountInfo| {
+      Processor::load_mail_account(account).unwrap().inbox[0]
+        .labels
+        .clone()
+    };
+
+    Processor::process_add_label(&account, "1", String::from("work"), &program_id).unwrap();
+    Processor::process_add_label(&account, "1", String::from("work"), &program_id).unwrap();
+    Processor::process_add_label(&account, "1", String::from("family"), &program_id).unwrap();
+    assert_eq!(labels(&account), vec!["work", "family"]);
+    let labelled = Processor::load_mail_account(&account).unwrap().inbox[0].clone();
+    assert_eq!(labelled.byte_size, stamped(labelled.clone()).byte_size);
+
+    Processor::process_remove_label(&account, "1", "work", &program_id).unwrap();
+    assert_eq!(labels(&account), vec!["family"]);
+    let unlabelled = Processor::load_mail_account(&account).unwrap().inbox[0].clone();
+    assert_eq!(unlabelled.byte_size, stamped(unlabelled.clone()).byte_size);
+
+    assert_eq!(
+      Processor::process_add_label(&account, "2", String::from("work"), &program_id),
+      Err(MailNotFound.into())
+    );
+
+    for index in 1..MAX_LABELS {
+      Processor::process_add_label(&account, "1", format!("label-{}", index), &program_id).unwrap();
+    }
+    assert_eq!(labels(&account).len(), MAX_LABELS);
+
+    assert_eq!(
+      Processor::process_add_label(&account, "1", String::from("one-too-many"), &program_id),
+      Err(TooManyLabels.into())
+    );
+    Processor::process_add_label(&account, "1", String::from("family"), &program_id).unwrap();
+  }
+
+  #[test]
+  fn test_send_mail_receiver_consent() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let receiver_owner_key = Pubkey::new_unique();
+    let mut receiver_owner_lamports = 0;
+    let mut receiver_owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_owner_account = AccountInfo::new(
+      &receiver_owner_key,
+      true,
+      false,
+      &mut receiver_owner_lamports,
+      &mut receiver_owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&receiver_owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mut unsigned_receiver_owner = receiver_owner_account.clone();
+    unsigned_receiver_owner.is_signer = false;
+    let mut signed_receiver = receiver_account.clone();
+    signed_receiver.is_signer = true;
+    for without_consent in vec![
+      accounts.clone(),
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        owner_account.clone(),
+        unsigned_receiver_owner,
+      ],
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        owner_account.clone(),
+        signed_receiver,
+      ],
+    ] {
+      assert_eq!(
+        Processor::process_send_mail(&without_consent, &mail, true, 0, false, false, &program_id),
+        Err(ReceiverConsentRequired.into())
+      );
+    }
+    assert!(Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox
+      .is_empty());
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+      receiver_owner_account.clone(),
+    ];
+
+    let consented = Mail {
+      id: String::from("2"),
+      ..mail.clone()
+    };
+    Processor::process_send_mail(&accounts, &consented, true, 1, false, false, &program_id)
+      .unwrap();
+
+    let inbox = Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox;
+    assert_eq!(inbox, vec![delivered(mail), delivered(consented)]);
+  }
+
+  #[test]
+  fn test_undo_send() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    Processor::process_send_mail(&accounts, &mail("1"), false, 0, false, false, &program_id)
+      .unwrap();
+    Processor::process_send_mail(&accounts, &mail("2"), false, 1, false, false, &program_id)
+      .unwrap();
+
+    let sender_data = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(
+      sender_data.pending,
+      vec![(mail("1"), NOW), (mail("2"), NOW)]
+    );
+
+    let wrong_receiver = vec![
+      sender_account.clone(),
+      sender_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_undo_send(&wrong_receiver, "1", &program_id),
+      Err(RecipientMismatch.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for unauthorized in vec![
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        unsigned_owner,
+      ],
+      vec![
+        sender_account.clone(),
+        receiver_account.clone(),
+        receiver_account.clone(),
+      ],
+    ] {
+      assert_eq!(
+        Processor::process_undo_send(&unauthorized, "1", &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+
+    Processor::process_undo_send(&accounts, "1", &program_id).unwrap();
+
+    let sender_data = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(sender_data.sent, vec![stamped(mail("2"))]);
+    assert_eq!(sender_data.pending, vec![(mail("2"), NOW)]);
+
+    let mut receiver_data = Processor::load_mail_account(&receiver_account).unwrap();
+    assert_eq!(receiver_data.inbox, vec![delivered(mail("2"))]);
+
+    assert_eq!(
+      Processor::process_undo_send(&accounts, "1", &program_id),
+      Err(MailNotFound.into())
+    );
+
+    receiver_data.inbox[0].from_address = Pubkey::new_unique().to_string();
+    Processor::store_mail_account(&receiver_account, &mut receiver_data).unwrap();
+    assert_eq!(
+      Processor::process_undo_send(&accounts, "2", &program_id),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&sender_account)
+        .unwrap()
+        .pending,
+      vec![(mail("2"), NOW)]
+    );
+  }
+
+  #[test]
+  fn test_undo_send_expired() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let mut sender_mailbox = MailAccount {
+      sent: vec![mail.clone()],
+      pending: vec![(mail.clone(), NOW - UNDO_WINDOW_SECS - 1)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut sender_mailbox).unwrap();
+
+    let mut receiver_mailbox = MailAccount {
+      inbox: vec![mail.clone()],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&receiver_account, &mut receiver_mailbox).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    assert_eq!(
+      Processor::process_undo_send(&accounts, "1", &program_id),
+      Err(UndoWindowExpired.into())
+    );
+
+    let receiver_data = Processor::load_mail_account(&receiver_account).unwrap();
+    assert_eq!(receiver_data.inbox, vec![mail]);
+  }
+
+  #[test]
+  fn test_get_message() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 200];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, subject: &str| Mail {
+      id: String::from(id),
+      subject: String::from(subject),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1", "First"), mail("2", "Second")],
+      sent: vec![mail("3", "Third")],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+
+    Processor::process_get_message(&accounts, "3", &program_id).unwrap();
+
+    let data_length = DataLength::try_from_slice(&scratch_account.data.borrow()[..4]).unwrap();
+    let length = usize::try_from(data_length.length + 4).unwrap();
+    assert_eq!(
+      Mail::try_from_slice(&scratch_account.data.borrow()[4..length]).unwrap(),
+      mail("3", "Third")
+    );
+
+    Processor::process_get_message(&accounts, "2", &program_id).unwrap();
+
+    let data_length = DataLength::try_from_slice(&scratch_account.data.borrow()[..4]).unwrap();
+    let length = usize::try_from(data_length.length + 4).unwrap();
+    assert_eq!(
+      Mail::try_from_slice(&scratch_account.data.borrow()[4..length]).unwrap(),
+      mail("2", "Second")
+    );
+
+    assert_eq!(
+      Processor::process_get_message(&accounts, "4", &program_id),
+      Err(MailNotFound.into())
+    );
+  }
+
+  #[test]
+  fn test_get_inbox_page() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 500];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2"), mail("3")],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+
+    let read_page = || {
+      let data_length = DataLength::try_from_slice(&scratch_account.data.borrow()[..4]).unwrap();
+      let length = usize::try_from(data_length.length + 4).unwrap();
+      Page::try_from_slice(&scratch_account.data.borrow()[4..length]).unwrap()
+    };
+
+    Processor::process_get_inbox_page(&accounts, 0, 2, &program_id).unwrap();
+    assert_eq!(
+      read_page(),
+      Page {
+        total: 3,
+        next_offset: 2,
+        mails: vec![mail("1"), mail("2")],
+      }
+    );
+
+    Processor::process_get_inbox_page(&accounts, 2, 2, &program_id).unwrap();
+    assert_eq!(
+      read_page(),
+      Page {
+        total: 3,
+        next_offset: 3,
+        mails: vec![mail("3")],
+      }
+    );
+  }
+  #[test]
+  fn test_account_smaller_than_header() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let (key, _) = find_mailbox_address(&owner_key, &program_id);
+    let mut lamports = 0;
+    let mut data = [0; 2];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let system_key = system_program::id();
+    let mut system_lamports = 0;
+    let mut system_data = [0; 0];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let system_account = AccountInfo::new(
+      &system_key,
+      false,
+      false,
+      &mut system_lamports,
+      &mut system_data,
+      &system_key,
+      true,
+      Epoch::default(),
+    );
+
+    let accounts = vec![account.clone(), owner_account.clone(), system_account];
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![receiver_account.clone(), account.clone(), owner_account];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    assert_eq!(*account.data.borrow(), &[0, 0]);
+  }
+
+  #[test]
+  fn test_transfer_mailbox() {
+    let program_id = Pubkey::default();
+    let mailbox_key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let new_owner_key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [];
+    let mut new_owner_lamports = 0;
+    let mut new_owner_data = [];
+
+    let mail_account_info = AccountInfo::new(
+      &mailbox_key,
+      false,
+      true,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut owner_account = AccountInfo::new(
+      &owner_key,
+      false,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let new_owner_account = AccountInfo::new(
+      &new_owner_key,
+      true,
+      false,
+      &mut new_owner_lamports,
+      &mut new_owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), owner_account.clone()];
+    assert_eq!(
+      Processor::process_transfer_mailbox(&accounts, new_owner_key.to_string(), &program_id),
+      Err(Unauthorized.into())
+    );
+
+    let accounts = vec![mail_account_info.clone(), new_owner_account.clone()];
+    assert_eq!(
+      Processor::process_transfer_mailbox(&accounts, new_owner_key.to_string(), &program_id),
+      Err(Unauthorized.into())
+    );
+
+    owner_account.is_signer = true;
+    let accounts = vec![mail_account_info.clone(), owner_account.clone()];
+    assert_eq!(
+      Processor::process_transfer_mailbox(&accounts, String::from("not-a-pubkey"), &program_id),
+      Err(InvalidAddress.into())
+    );
+
+    Processor::process_transfer_mailbox(&accounts, new_owner_key.to_string(), &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&mail_account_info).unwrap();
+    assert_eq!(mail_account.owner_pubkey, new_owner_key.to_string());
+  }
+
+  #[test]
+  fn test_send_batch() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let first_key = Pubkey::new_unique();
+    let second_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut first_lamports = 0;
+    let mut first_data = [0; 1000];
+    let mut second_lamports = 0;
+    let mut second_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let first_account = AccountInfo::new(
+      &first_key,
+      false,
+      true,
+      &mut first_lamports,
+      &mut first_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let second_account = AccountInfo::new(
+      &second_key,
+      false,
+      true,
+      &mut second_lamports,
+      &mut second_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, to: &Pubkey, subject: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: to.to_string(),
+      subject: String::from(subject),
+      ..Mail::default()
+    };
+    let mails = vec![
+      mail("1", &first_key, "Hey Mike"),
+      mail("2", &second_key, "Hey Jane"),
+    ];
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      first_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_batch(&accounts, &mails, &program_id),
+      Err(AccountMailCountMismatch.into())
+    );
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      first_account.clone(),
+      second_account.clone(),
+    ];
+    for account in &accounts[2..] {
+      Processor::store_mail_account(account, &mut MailAccount::default()).unwrap();
+    }
+
+    let swapped = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      second_account.clone(),
+      first_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_batch(&swapped, &mails, &program_id),
+      Err(RecipientMismatch.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let unauthorized = vec![
+      sender_account.clone(),
+      unsigned_owner,
+      first_account.clone(),
+      second_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_batch(&unauthorized, &mails, &program_id),
+      Err(Unauthorized.into())
+    );
+
+    Processor::process_send_batch(&accounts, &mails, &program_id).unwrap();
+
+    assert_eq!(
+      Processor::load_mail_account(&first_account).unwrap().inbox,
+      vec![delivered(mails[0].clone())]
+    );
+    assert_eq!(
+      Processor::load_mail_account(&second_account).unwrap().inbox,
+      vec![delivered(mails[1].clone())]
+    );
+    let mails: Vec<Mail> = mails.into_iter().map(stamped).collect();
+    assert_eq!(
+      Processor::load_mail_account(&sender_account).unwrap().sent,
+      mails
+    );
+  }
+
+  #[test]
+  fn test_set_read_cursor() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: (1..=3)
+        .map(|id| Mail {
+          id: id.to_string(),
+          ..Mail::default()
+        })
+        .collect(),
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_set_read_cursor(&[account.clone(), unsigned_owner], 2, &program_id),
+      Err(Unauthorized.into())
+    );
+
+    Processor::process_set_read_cursor(&accounts, 2, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account)
+        .unwrap()
+        .last_read_index,
+      2
+    );
+
+    assert_eq!(
+      Processor::process_set_read_cursor(&accounts, 3, &program_id),
+      Err(IndexOutOfBounds.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&account)
+        .unwrap()
+        .last_read_index,
+      2
+    );
+
+    // Removing inbox mail pulls the cursor back onto the inbox.
+    Processor::process_delete_mail(&accounts, "3", &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account)
+        .unwrap()
+        .last_read_index,
+      1
+    );
+  }
+
+  #[test]
+  fn test_move_mail() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1")],
+      drafts: vec![mail("2")],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    Processor::process_move_mail(&accounts, "1", Folder::Inbox, Folder::Archive, &program_id)
+      .unwrap();
+    Processor::process_move_mail(&accounts, "2", Folder::Drafts, Folder::Sent, &program_id)
+      .unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert!(mail_account.inbox.is_empty());
+    assert!(mail_account.drafts.is_empty());
+    assert_eq!(mail_account.archive, vec![mail("1")]);
+    assert_eq!(mail_account.sent, vec![mail("2")]);
+
+    assert_eq!(
+      Processor::process_move_mail(&accounts, "1", Folder::Inbox, Folder::Archive, &program_id),
+      Err(MailNotFound.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_move_mail(
+        &[account.clone(), unsigned_owner],
+        "1",
+        Folder::Archive,
+        Folder::Inbox,
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+  }
+
+  #[test]
+  fn test_trash() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2")],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    Processor::process_delete_mail(&accounts, "1", &program_id).unwrap();
+    Processor::process_delete_mail(&accounts, "2", &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert!(mail_account.inbox.is_empty());
+    assert_eq!(mail_account.trash, vec![mail("1"), mail("2")]);
+
+    Processor::process_restore_from_trash(&accounts, "2", &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox, vec![mail("2")]);
+    assert_eq!(mail_account.trash, vec![mail("1")]);
+
+    Processor::process_empty_trash(&accounts, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox, vec![mail("2")]);
+    assert!(mail_account.trash.is_empty());
+
+    assert_eq!(
+      Processor::process_restore_from_trash(&accounts, "1", &program_id),
+      Err(MailNotFound.into())
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for accounts in vec![
+      vec![account.clone(), unsigned_owner],
+      vec![account.clone(), account.clone()],
+    ] {
+      assert_eq!(
+        Processor::process_delete_mail(&accounts, "2", &program_id),
+        Err(Unauthorized.into())
+      );
+      assert_eq!(
+        Processor::process_restore_from_trash(&accounts, "1", &program_id),
+        Err(Unauthorized.into())
+      );
+      assert_eq!(
+        Processor::process_empty_trash(&accounts, &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().inbox,
+      vec![mail("2")]
+    );
+  }
+  #[test]
+  fn test_send_mail_byte_size() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      body: String::from("Body text with some characters"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+
+    let delivered = Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox
+      .remove(0);
+    let sent = Processor::load_mail_account(&sender_account)
+      .unwrap()
+      .sent
+      .remove(0);
+
+    for stored in [delivered, sent] {
+      assert_ne!(stored.byte_size, 0);
+      assert_eq!(
+        usize::try_from(stored.byte_size).unwrap(),
+        get_instance_packed_len(&stored).unwrap()
+      );
+      assert_eq!(
+        usize::try_from(stored.byte_size).unwrap(),
+        get_instance_packed_len(&mail).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_send_to_group() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let group_key = Pubkey::new_unique();
+    let first_key = Pubkey::new_unique();
+    let second_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut group_lamports = 0;
+    let mut group_data = [0; 200];
+    let mut first_lamports = 0;
+    let mut first_data = [0; 1000];
+    let mut second_lamports = 0;
+    let mut second_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let group_account = AccountInfo::new(
+      &group_key,
+      false,
+      false,
+      &mut group_lamports,
+      &mut group_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let first_account = AccountInfo::new(
+      &first_key,
+      false,
+      true,
+      &mut first_lamports,
+      &mut first_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let second_account = AccountInfo::new(
+      &second_key,
+      false,
+      true,
+      &mut second_lamports,
+      &mut second_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+
+    let group = GroupAccount {
+      members: vec![first_key.to_string(), second_key.to_string()],
+      ..GroupAccount::default()
+    };
+    Processor::write_account(&group_account, &group).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: group_key.to_string(),
+      subject: String::from("Hey everyone"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      group_account.clone(),
+      second_account.clone(),
+      first_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_to_group(&accounts, &mail, &program_id),
+      Err(GroupMemberMismatch.into())
+    );
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      group_account.clone(),
+      first_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_to_group(&accounts, &mail, &program_id),
+      Err(GroupMemberMismatch.into())
+    );
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      group_account.clone(),
+      first_account.clone(),
+      second_account.clone(),
+    ];
+    for member_account in [&first_account, &second_account] {
+      Processor::store_mail_account(member_account, &mut MailAccount::default()).unwrap();
+    }
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let unauthorized = vec![
+      sender_account.clone(),
+      unsigned_owner,
+      group_account.clone(),
+      first_account.clone(),
+      second_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_to_group(&unauthorized, &mail, &program_id),
+      Err(Unauthorized.into())
+    );
+    Processor::process_send_to_group(&accounts, &mail, &program_id).unwrap();
+
+    for member_account in [&first_account, &second_account] {
+      assert_eq!(
+        Processor::load_mail_account(member_account).unwrap().inbox,
+        vec![delivered(mail.clone())]
+      );
+    }
+    assert_eq!(
+      Processor::load_mail_account(&sender_account).unwrap().sent,
+      vec![stamped(mail)]
+    );
+  }
+
+  #[test]
+  fn test_set_group_members() {
+    let program_id = Pubkey::new_unique();
+    let group_key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let stranger_key = Pubkey::new_unique();
+    let mut group_lamports = 0;
+    let mut group_data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let mut stranger_lamports = 0;
+    let mut stranger_data = [0; 0];
+
+    let group_account = AccountInfo::new(
+      &group_key,
+      false,
+      true,
+      &mut group_lamports,
+      &mut group_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      false,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let stranger_account = AccountInfo::new(
+      &stranger_key,
+      true,
+      false,
+      &mut stranger_lamports,
+      &mut stranger_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let members = vec![
+      Pubkey::new_unique().to_string(),
+      Pubkey::new_unique().to_string(),
+    ];
+
+    let accounts = vec![group_account.clone(), owner_account.clone()];
+    assert_eq!(
+      Processor::process_set_group_members(&accounts, members.clone(), &program_id),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(*group_account.data.borrow(), &[0; 1000][..]);
+
+    let mut owner_account = owner_account;
+    owner_account.is_signer = true;
+    let accounts = vec![group_account.clone(), owner_account.clone()];
+    assert_eq!(
+      Processor::process_set_group_members(&accounts, members.clone(), &program_id),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(*group_account.data.borrow(), &[0; 1000][..]);
+
+    // The group account signs only the claim; later calls need just the owner.
+    let mut signed_group = group_account.clone();
+    signed_group.is_signer = true;
+    let claim_accounts = vec![signed_group, owner_account];
+    Processor::process_set_group_members(&claim_accounts, members.clone(), &program_id).unwrap();
+    assert_eq!(
+      Processor::load_with_header::<GroupAccount>(&group_account).unwrap(),
+      GroupAccount {
+        owner: owner_key.to_string(),
+        members: members.clone(),
+      }
+    );
+
+    let stranger_accounts = vec![group_account.clone(), stranger_account];
+    assert_eq!(
+      Processor::process_set_group_members(&stranger_accounts, Vec::new(), &program_id),
+      Err(Unauthorized.into())
+    );
+
+    assert_eq!(
+      Processor::process_set_group_members(&accounts, vec![String::from("mike")], &program_id),
+      Err(InvalidAddress.into())
+    );
+    assert_eq!(
+      Processor::process_set_group_members(
+        &accounts,
+        vec![members[0].clone(); MAX_RECIPIENTS + 1],
+        &program_id
+      ),
+      Err(TooManyRecipients.into())
+    );
+
+    Processor::process_set_group_members(&accounts, members[1..].to_vec(), &program_id).unwrap();
+    assert_eq!(
+      Processor::load_with_header::<GroupAccount>(&group_account).unwrap(),
+      GroupAccount {
+        owner: owner_key.to_string(),
+        members: members[1..].to_vec(),
+      }
+    );
+  }
+
+  #[test]
+  fn test_send_mail_not_initialized() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    assert!(!Processor::is_initialized(&sender_account));
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(AccountNotInitialized.into())
+    );
+    assert!(!Processor::is_initialized(&receiver_account));
+
+    // Every fan-out path records its sent copy through the same check.
+    assert_eq!(
+      Mailboxes::default().record_sent(&sender_account, mail.clone()),
+      Err(AccountNotInitialized.into())
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+
+    assert!(Processor::is_initialized(&sender_account));
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(ReceiverNotInitialized.into())
+    );
+    assert!(!Processor::is_initialized(&receiver_account));
+    assert!(Processor::load_mail_account(&sender_account)
+      .unwrap()
+      .sent
+      .is_empty());
+
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .last(),
+      Some(&delivered(mail))
+    );
+  }
+
+  #[test]
+  fn test_send_mail_nonce() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 1, false, false, &program_id),
+      Err(InvalidNonce.into())
+    );
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&sender_account)
+        .unwrap()
+        .send_nonce,
+      1
+    );
+
+    let next = Mail {
+      id: String::from("2"),
+      ..mail.clone()
+    };
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &next, false, 0, false, false, &program_id),
+      Err(InvalidNonce.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      1
+    );
+
+    Processor::process_send_mail(&accounts, &next, false, 1, false, false, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&sender_account)
+        .unwrap()
+        .send_nonce,
+      2
+    );
+  }
+
+  #[test]
+  fn test_bytes_used() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      body: String::from("Body text with some characters"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+
+    for account in [&sender_account, &receiver_account] {
+      let data_length = DataLength::try_from_slice(&account.data.borrow()[..4]).unwrap();
+      let mail_account = Processor::load_mail_account(account).unwrap();
+      assert_eq!(mail_account.bytes_used, data_length.length);
+    }
+
+    let receiver_mailbox = Processor::load_mail_account(&receiver_account).unwrap();
+    let after_send = receiver_mailbox.bytes_used;
+    assert!(after_send >= stamped(mail.clone()).byte_size);
+
+    let mut inbox_read = receiver_mailbox;
+    inbox_read.inbox[0].read = true;
+    Processor::store_mail_account(&receiver_account, &mut inbox_read).unwrap();
+    let delete_accounts = vec![receiver_account.clone(), owner_account.clone()];
+    Processor::process_delete_all_read(&delete_accounts, &program_id).unwrap();
+
+    let receiver_mailbox = Processor::load_mail_account(&receiver_account).unwrap();
+    assert_eq!(
+      receiver_mailbox.bytes_used,
+      u32::try_from(get_instance_packed_len(&owned_by(&owner_key)).unwrap()).unwrap()
+    );
+    assert!(receiver_mailbox.bytes_used < after_send);
+  }
+
+  #[test]
+  fn test_send_mail_quota_exceeded() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = vec![0; MAILBOX_LEN];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+
+    // Fill the receiver to within one filler mail of its quota.
+    let filler = |id: usize| {
+      stamped(Mail {
+        id: format!("filler-{}", id),
+        body: "x".repeat(400),
+        ..Mail::default()
+      })
+    };
+    let mut nearly_full = MailAccount::default();
+    while nearly_full.total_bytes() + filler(0).byte_size <= MAX_ACCOUNT_BYTES {
+      nearly_full.inbox.push(filler(nearly_full.inbox.len()));
+    }
+    Processor::store_mail_account(&receiver_account, &mut nearly_full).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      body: "x".repeat(500),
+      ..Mail::default()
+    };
+
+    // The account itself still has room, so only the quota stops this mail.
+    let receiver_mailbox = Processor::load_mail_account(&receiver_account).unwrap();
+    let mail_size = usize::try_from(delivered(mail.clone()).byte_size).unwrap();
+    assert!(Processor::free_bytes(&receiver_account, &receiver_mailbox).unwrap() > mail_size);
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(QuotaExceeded.into())
+    );
+    assert!(Processor::load_mail_account(&sender_account)
+      .unwrap()
+      .sent
+      .is_empty());
+  }
+
+  #[test]
+  fn test_recall_mail() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let stranger_key = Pubkey::new_unique();
+    let mut stranger_lamports = 0;
+    let mut stranger_data = [0; 1000];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let stranger_account = AccountInfo::new(
+      &stranger_key,
+      false,
+      true,
+      &mut stranger_lamports,
+      &mut stranger_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owned = || MailAccount {
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut owned()).unwrap();
+    Processor::store_mail_account(&stranger_account, &mut owned()).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned()).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    let recall_accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    Processor::process_send_mail(&accounts, &mail("1"), false, 0, false, false, &program_id)
+      .unwrap();
+    Processor::process_send_mail(&accounts, &mail("2"), false, 1, false, false, &program_id)
+      .unwrap();
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    let unsigned_accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      unsigned_owner,
+    ];
+    assert_eq!(
+      Processor::process_recall_mail(&unsigned_accounts, "1", &program_id),
+      Err(Unauthorized.into())
+    );
+
+    let stranger_accounts = vec![stranger_account, receiver_account.clone(), owner_account];
+    assert_eq!(
+      Processor::process_recall_mail(&stranger_accounts, "1", &program_id),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      2
+    );
+
+    Processor::process_recall_mail(&recall_accounts, "1", &program_id).unwrap();
+
+    let sender_data = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(sender_data.sent, vec![stamped(mail("2"))]);
+    assert_eq!(sender_data.pending, vec![(mail("2"), NOW)]);
+
+    let mut receiver_data = Processor::load_mail_account(&receiver_account).unwrap();
+    assert_eq!(receiver_data.inbox, vec![delivered(mail("2"))]);
+
+    receiver_data.inbox[0].read = true;
+    Processor::store_mail_account(&receiver_account, &mut receiver_data).unwrap();
+
+    assert_eq!(
+      Processor::process_recall_mail(&recall_accounts, "2", &program_id),
+      Err(AlreadyRead.into())
+    );
+    assert_eq!(
+      Processor::load_mail_account(&sender_account).unwrap().sent,
+      vec![stamped(mail("2"))]
+    );
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      1
+    );
+
+    assert_eq!(
+      Processor::process_recall_mail(&recall_accounts, "1", &program_id),
+      Err(MailNotFound.into())
+    );
+  }
+
+  #[test]
+  fn test_send_mail_allowlist() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    assert_eq!(
+      Processor::process_set_allowlist_mode(
+        &[receiver_account.clone(), sender_account.clone()],
+        true,
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+    let owned = vec![receiver_account.clone(), owner_account.clone()];
+    Processor::process_set_allowlist_mode(&owned, true, &program_id).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    let fan_out = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      receiver_account.clone(),
+    ];
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(SenderNotAllowlisted.into())
+    );
+    assert_eq!(
+      Processor::process_send_mail_bcc(&fan_out, &mail, &program_id),
+      Err(SenderNotAllowlisted.into())
+    );
+    assert_eq!(
+      Processor::process_send_batch(&fan_out, std::slice::from_ref(&mail), &program_id),
+      Err(SenderNotAllowlisted.into())
+    );
+    assert!(Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox
+      .is_empty());
+
+    Processor::process_add_contact(
+      &owned,
+      String::from("sender"),
+      sender_key.to_string(),
+      &program_id,
+    )
+    .unwrap();
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+    // Mail from a contact is classified one priority level up.
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox,
+      vec![delivered(Mail {
+        priority: 1,
+        ..mail
+      })]
+    );
+  }
+
+  #[test]
+  fn test_corrupted_data_length() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 100];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let data_length = DataLength {
+      length: u32::MAX - 1,
+    };
+    data_length
+      .serialize(&mut &mut account.data.borrow_mut()[..4])
+      .unwrap();
+
+    assert_eq!(
+      Processor::load_mail_account(&account).err(),
+      Some(ArithmeticOverflow.into())
+    );
+
+    let data_length = DataLength { length: 1000 };
+    data_length
+      .serialize(&mut &mut account.data.borrow_mut()[..4])
+      .unwrap();
+
+    assert_eq!(
+      Processor::load_mail_account(&account).err(),
+      Some(ProgramError::InvalidAccountData)
+    );
+  }
+
+  #[test]
+  fn test_validate_send() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 300];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let sender_before = sender_account.data.borrow().to_vec();
+    let receiver_before = receiver_account.data.borrow().to_vec();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+    Processor::process_validate_send(&accounts, &mail, &program_id).unwrap();
+
+    let too_large = Mail {
+      body: "a".repeat(200),
+      ..mail
+    };
+    assert_eq!(
+      Processor::process_validate_send(&accounts, &too_large, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    assert_eq!(*sender_account.data.borrow(), &sender_before[..]);
+    assert_eq!(*receiver_account.data.borrow(), &receiver_before[..]);
+  }
+
+  #[test]
+  fn test_repair_header() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: vec![Mail {
+        id: String::from("1"),
+        subject: String::from("Hey Mike!!!"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let stored = account.data.borrow().to_vec();
+
+    for corrupted in [1u32, 900] {
+      DataLength { length: corrupted }
+        .serialize(&mut &mut account.data.borrow_mut()[..4])
+        .unwrap();
+      assert!(Processor::load_mail_account(&account).is_err());
+
+      Processor::process_repair_header(&account, &program_id).unwrap();
+
+      assert_eq!(*account.data.borrow(), &stored[..]);
+      assert_eq!(
+        Processor::load_mail_account(&account).unwrap().inbox,
+        mail_account.inbox
+      );
+    }
+
+    account.data.borrow_mut()[4..].fill(0xff);
+    assert_eq!(
+      Processor::process_repair_header(&account, &program_id),
+      Err(InvalidAccountData.into())
+    );
+  }
+
+  #[test]
+  fn test_send_mail_delivered_at() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      sent_date: String::from("1/1/2020, 9:00:00 AM"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id).unwrap();
+
+    let received = &Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox[0];
+    assert_eq!(received.delivered_at, NOW);
+    assert_eq!(received.sent_date, mail.sent_date);
+
+    let sent = &Processor::load_mail_account(&sender_account).unwrap().sent[0];
+    assert_eq!(sent.delivered_at, 0);
+  }
+
+  #[test]
+  fn test_write_account() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 200];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let group = GroupAccount {
+      members: vec![Pubkey::new_unique().to_string()],
+      ..GroupAccount::default()
+    };
+    Processor::write_account(&account, &group).unwrap();
+
+    let data_length = DataLength::try_from_slice(&account.data.borrow()[..4]).unwrap();
+    assert_eq!(
+      usize::try_from(data_length.length).unwrap(),
+      get_instance_packed_len(&group).unwrap()
+    );
+    assert_eq!(
+      Processor::load_with_header::<GroupAccount>(&account).unwrap(),
+      group
+    );
+
+    let too_large = GroupAccount {
+      members: vec![Pubkey::new_unique().to_string(); 10],
+      ..GroupAccount::default()
+    };
+    let before = account.data.borrow().to_vec();
+    assert_eq!(
+      Processor::write_account(&account, &too_large),
+      Err(AccountTooSmall.into())
+    );
+    assert_eq!(*account.data.borrow(), &before[..]);
+  }
+
+  #[cfg(feature = "client")]
+  #[test]
+  fn test_send_mail_verify_signature() {
+    use crate::client::{send_signed_mail_ix, verify_signature_ix};
+    use solana_program::message::Message;
+
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let instructions_key = instructions::id();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let mut instructions_lamports = 0;
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      signature: vec![7; 64],
+      ..Mail::default()
+    };
+    let forged = Mail {
+      signature: vec![8; 64],
+      ..mail.clone()
+    };
+    let by_mailbox = Mail {
+      signature: vec![9; 64],
+      ..mail.clone()
+    };
+
+    // The runtime rejects the transaction if the Ed25519 check itself fails, so only the
+    // match between that check and the mail is exercised here.
+    let mut instructions_data = Message::new(
+      &[
+        verify_signature_ix(&sender_key, &by_mailbox),
+        verify_signature_ix(&owner_key, &mail),
+        send_signed_mail_ix(
+          &program_id,
+          &sender_key,
+          &owner_key,
+          &receiver_key,
+          &mail,
+          0,
+        ),
+      ],
+      None,
+    )
+    .serialize_instructions();
+    instructions_data.extend_from_slice(&2u16.to_le_bytes());
+
+    let instructions_account = AccountInfo::new(
+      &instructions_key,
+      false,
+      false,
+      &mut instructions_lamports,
+      &mut instructions_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut MailAccount::default()).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+      instructions_account,
+    ];
+
+    for unverified in [&forged, &by_mailbox] {
+      assert_eq!(
+        Processor::process_send_mail(&accounts, unverified, false, 0, true, false, &program_id),
+        Err(InvalidSignature.into())
+      );
+    }
+    assert_eq!(
+      Processor::process_send_mail(&accounts[..3], &mail, false, 0, true, false, &program_id),
+      Err(ProgramError::NotEnoughAccountKeys)
+    );
+
+    Processor::process_send_mail(&accounts, &mail, false, 0, true, false, &program_id).unwrap();
+
+    let inbox = Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox;
+    assert_eq!(inbox, vec![delivered(mail)]);
+  }
+
+  #[test]
+  fn test_scrub_tail() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      subject: String::from("Hey Mike!!!"),
+      read,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1", true), mail("2", false), mail("3", true)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+    let full_length = DataLength::try_from_slice(&account.data.borrow()[..4])
+      .unwrap()
+      .length;
+
+    Processor::process_delete_all_read(&accounts, &program_id).unwrap();
+
+    let deleted = account.data.borrow().to_vec();
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox, vec![mail("2", false)]);
+
+    let length = DataLength::try_from_slice(&deleted[..4]).unwrap().length;
+    assert!(length < full_length);
+    assert_eq!(
+      length as usize,
+      get_instance_packed_len(&mail_account).unwrap()
+    );
+    let end = 4 + length as usize;
+    // DeleteAllRead leaves the bytes of the old, longer mailbox after the new end.
+    assert!(deleted[end..4 + full_length as usize]
+      .iter()
+      .any(|byte| *byte != 0));
+
+    Processor::process_scrub_tail(&account, &program_id).unwrap();
+
+    let scrubbed = account.data.borrow().to_vec();
+    assert_eq!(scrubbed[..end], deleted[..end]);
+    assert!(scrubbed[end..].iter().all(|byte| *byte == 0));
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().inbox,
+      mail_account.inbox
+    );
+  }
+
+  #[test]
+  fn test_push_accounts_for_both_folders() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::new_unique();
+    let mail = stamped(Mail {
+      id: String::from("1"),
+      from_address: key.to_string(),
+      subject: String::from("Note to self"),
+      body: "a".repeat(100),
+      ..Mail::default()
+    });
+    let mut lamports = 0;
+    let empty_length = 4 + get_instance_packed_len(&MailAccount::default()).unwrap();
+    // Room for the sent copy or the inbox copy, but not both.
+    let mut data = vec![0; empty_length + mail.byte_size as usize + 10];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&account, &mut MailAccount::default()).unwrap();
+    let before = account.data.borrow().to_vec();
+
+    let mut mailboxes = Mailboxes::default();
+    mailboxes.record_sent(&account, mail.clone()).unwrap();
+    assert_eq!(
+      mailboxes.deliver(&account, &account, mail),
+      Err(AccountTooSmall.into())
+    );
+
+    assert_eq!(*account.data.borrow(), &before[..]);
+  }
+
+  #[test]
+  fn test_get_sent_status() {
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 100];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      false,
+      false,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      false,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &receiver_key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(
+      &sender_account,
+      &mut MailAccount {
+        sent: vec![mail("1"), mail("2")],
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        inbox: vec![mail("1")],
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      scratch_account.clone(),
+    ];
+
+    let read_status = || SentStatus::try_from_slice(&scratch_account.data.borrow()[4..6]).unwrap();
+
+    Processor::process_get_sent_status(&accounts, "1", &program_id).unwrap();
+    assert_eq!(
+      read_status(),
+      SentStatus {
+        delivered: true,
+        read: false,
+      }
+    );
+
+    Processor::process_get_sent_status(&accounts, "2", &program_id).unwrap();
+    assert_eq!(read_status(), SentStatus::default());
+
+    assert_eq!(
+      Processor::process_get_sent_status(&accounts, "3", &program_id),
+      Err(MailNotFound.into())
+    );
+  }
+
+  #[test]
+  fn test_auto_archive() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let day = 86_400;
+    let mail = |id: &str, read: bool, delivered_at: u64| Mail {
+      id: String::from(id),
+      read,
+      delivered_at,
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![
+        mail("old-read", true, NOW - 10 * day),
+        mail("old-unread", false, NOW - 10 * day),
+        mail("recent-read", true, NOW - day),
+        mail("undated-read", true, 0),
+        mail("already-archived", true, NOW - 10 * day),
+      ],
+      archive: vec![mail("already-archived", true, NOW - 20 * day)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    Processor::process_auto_archive(&account, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().inbox.len(),
+      5
+    );
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_set_auto_archive(&[account.clone(), unsigned_owner], Some(7), &program_id),
+      Err(Unauthorized.into())
+    );
+    Processor::process_set_auto_archive(&accounts, Some(7), &program_id).unwrap();
+    Processor::process_auto_archive(&account, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    let ids =
+      |folder: &[Mail]| -> Vec<String> { folder.iter().map(|mail| mail.id.clone()).collect() };
+    assert_eq!(
+      ids(&mail_account.inbox),
+      vec![
+        "old-unread",
+        "recent-read",
+        "undated-read",
+        "already-archived"
+      ]
+    );
+    assert_eq!(
+      ids(&mail_account.archive),
+      vec!["already-archived", "old-read"]
+    );
+    assert_eq!(mail_account.auto_archive_days, Some(7));
+  }
+
+  #[cfg(feature = "normalize")]
+  #[test]
+  fn test_send_mail_normalize_subject() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Re\u{301}sume\u{301}\u{200b} attached"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(&accounts, &mail, false, 0, false, true, &program_id).unwrap();
+
+    let received = &Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox[0];
+    assert_eq!(received.subject, "Résumé attached");
+    let sent = &Processor::load_mail_account(&sender_account).unwrap().sent[0];
+    assert_eq!(sent.subject, "Résumé attached");
+  }
+
+  #[test]
+  fn test_forward_mail() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 2000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 2000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let original = Mail {
+      id: String::from("1"),
+      from_address: Pubkey::new_unique().to_string(),
+      to_address: sender_key.to_string(),
+      subject: String::from("Photos"),
+      body: String::from("From the trip"),
+      read: true,
+      acknowledged: true,
+      attachments: vec![String::from("https://example.com/photo.jpg")],
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      sent_at: NOW - 3_600,
+      expires_at: Some(NOW - 60),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(
+      &sender_account,
+      &mut MailAccount {
+        inbox: vec![original.clone()],
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+
+    assert_eq!(
+      Processor::process_forward_mail(
+        &accounts,
+        "2",
+        String::from("f1"),
+        receiver_key.to_string(),
+        false,
+        &program_id
+      ),
+      Err(MailNotFound.into())
+    );
+    assert_eq!(
+      Processor::process_forward_mail(
+        &accounts,
+        "1",
+        String::from("f1"),
+        sender_key.to_string(),
+        false,
+        &program_id
+      ),
+      Err(InvalidAddress.into())
+    );
+
+    Processor::process_forward_mail(
+      &accounts,
+      "1",
+      String::from("f1"),
+      receiver_key.to_string(),
+      false,
+      &program_id,
+    )
+    .unwrap();
+    Processor::process_forward_mail(
+      &accounts,
+      "1",
+      String::from("f2"),
+      receiver_key.to_string(),
+      true,
+      &program_id,
+    )
+    .unwrap();
+
+    let inbox = Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox;
+    assert_eq!(inbox.len(), 2);
+    for forwarded in &inbox {
+      assert_eq!(forwarded.from_address, sender_key.to_string());
+      assert_eq!(forwarded.to_address, receiver_key.to_string());
+      assert_eq!(forwarded.subject, "Fwd: Photos");
+      assert_eq!(forwarded.body, original.body);
+      assert!(!forwarded.read);
+      assert!(!forwarded.acknowledged);
+      assert_eq!(forwarded.sent_at, NOW);
+      assert!(forwarded.sent_date.is_empty());
+      assert_eq!(forwarded.expires_at, None);
+    }
+    assert_eq!(inbox[0].id, "f1");
+    assert_eq!(inbox[1].id, "f2");
+    assert_eq!(inbox[0].attachments, original.attachments);
+    assert!(inbox[1].attachments.is_empty());
+
+    let sent = Processor::load_mail_account(&sender_account).unwrap().sent;
+    assert_eq!(sent.len(), 2);
+  }
+
+  #[test]
+  fn test_check_owner() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let system_owner = system_program::id();
+    let other_owner = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 0];
+
+    let mut account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+    assert_eq!(Processor::check_owner(&account, &program_id), Ok(()));
+
+    account.owner = &system_owner;
+    assert_eq!(
+      Processor::check_owner(&account, &program_id),
+      Err(AccountNotAssigned.into())
+    );
+
+    account.owner = &other_owner;
+    assert_eq!(
+      Processor::check_owner(&account, &program_id),
+      Err(ProgramError::IncorrectProgramId)
+    );
+  }
+
+  #[test]
+  fn test_merge_mailbox() {
+    let program_id = Pubkey::default();
+    let source_key = Pubkey::new_unique();
+    let destination_key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let mut source_lamports = 5;
+    let mut source_data = [0; 1000];
+    let mut destination_lamports = 10;
+    let mut destination_data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let source_account = AccountInfo::new(
+      &source_key,
+      false,
+      true,
+      &mut source_lamports,
+      &mut source_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let destination_account = AccountInfo::new(
+      &destination_key,
+      false,
+      true,
+      &mut destination_lamports,
+      &mut destination_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut owner_account = AccountInfo::new(
+      &owner_key,
+      false,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+    let ids =
+      |folder: &[Mail]| -> Vec<String> { folder.iter().map(|mail| mail.id.clone()).collect() };
+
+    Processor::store_mail_account(
+      &source_account,
+      &mut MailAccount {
+        inbox: vec![mail("1"), mail("2")],
+        sent: vec![mail("3")],
+        archive: vec![mail("5")],
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    Processor::store_mail_account(
+      &destination_account,
+      &mut MailAccount {
+        inbox: vec![mail("2"), mail("4")],
+        archive: vec![mail("3")],
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    let accounts = vec![
+      source_account.clone(),
+      destination_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_merge_mailbox(&accounts, true, &program_id),
+      Err(Unauthorized.into())
+    );
+
+    owner_account.is_signer = true;
+    let accounts = vec![
+      source_account.clone(),
+      destination_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_merge_mailbox(&accounts, true, &program_id).unwrap();
+
+    let merged = Processor::load_mail_account(&destination_account).unwrap();
+    assert_eq!(ids(&merged.inbox), vec!["2", "4", "1"]);
+    assert!(merged.sent.is_empty());
+    assert_eq!(ids(&merged.archive), vec!["3", "5"]);
+
+    assert_eq!(source_account.lamports(), 0);
+    assert_eq!(destination_account.lamports(), 15);
+    assert!(source_account.data.borrow().iter().all(|byte| *byte == 0));
+  }
+
+  #[test]
+  fn test_send_mail_prunes_by_retention() {
+    stub_clock();
+
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 2000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, delivered_at: u64| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      delivered_at,
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        inbox: vec![
+          mail("expired", NOW - 7_200),
+          mail("fresh", NOW - 60),
+          mail("undated", 0),
+        ],
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    let fan_out = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      receiver_account.clone(),
+    ];
+    let inbox_ids = || -> Vec<String> {
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .iter()
+        .map(|mail| mail.id.clone())
+        .collect()
+    };
+
+    Processor::process_send_mail(
+      &accounts,
+      &mail("1", 0),
+      false,
+      0,
+      false,
+      false,
+      &program_id,
+    )
+    .unwrap();
+    assert_eq!(inbox_ids(), vec!["expired", "fresh", "undated", "1"]);
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for retention_accounts in vec![
+      vec![receiver_account.clone(), unsigned_owner],
+      vec![receiver_account.clone(), sender_account.clone()],
+    ] {
+      assert_eq!(
+        Processor::process_set_retention(&retention_accounts, Some(3_600), &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+
+    let retention_accounts = vec![receiver_account.clone(), owner_account.clone()];
+    Processor::process_set_retention(&retention_accounts, Some(3_600), &program_id).unwrap();
+    Processor::process_send_mail(
+      &accounts,
+      &mail("2", 0),
+      false,
+      1,
+      false,
+      false,
+      &program_id,
+    )
+    .unwrap();
+    assert_eq!(inbox_ids(), vec!["fresh", "undated", "1", "2"]);
+
+    Processor::process_set_retention(&retention_accounts, Some(30), &program_id).unwrap();
+    Processor::process_send_mail_bcc(&fan_out, &mail("3", 0), &program_id).unwrap();
+    assert_eq!(inbox_ids(), vec!["undated", "1", "2", "3"]);
+  }
+
+  #[test]
+  fn test_acknowledge_mail() {
+    let program_id = Pubkey::default();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      false,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Contract"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(
+      &sender_account,
+      &mut MailAccount {
+        sent: vec![mail.clone()],
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        inbox: vec![mail],
+        owner_pubkey: owner_key.to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    // A mailbox signing for itself is not its owner.
+    let mut signing_receiver = receiver_account.clone();
+    signing_receiver.is_signer = true;
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    for unauthorized in vec![
+      vec![
+        signing_receiver.clone(),
+        signing_receiver,
+        sender_account.clone(),
+      ],
+      vec![
+        receiver_account.clone(),
+        unsigned_owner,
+        sender_account.clone(),
+      ],
+    ] {
+      assert_eq!(
+        Processor::process_acknowledge_mail(&unauthorized, "1", &program_id),
+        Err(Unauthorized.into())
+      );
+    }
+
+    let accounts = vec![
+      receiver_account.clone(),
+      owner_account.clone(),
+      sender_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_acknowledge_mail(&accounts, "2", &program_id),
+      Err(MailNotFound.into())
+    );
+
+    Processor::process_acknowledge_mail(&accounts, "1", &program_id).unwrap();
+
+    assert!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox[0]
+        .acknowledged
+    );
+    assert!(Processor::load_mail_account(&sender_account).unwrap().sent[0].acknowledged);
+  }
+
+  #[test]
+  fn test_delete_thread() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, in_reply_to: Option<&str>| Mail {
+      id: String::from(id),
+      in_reply_to: in_reply_to.map(String::from),
+      ..Mail::default()
+    };
+    let ids =
+      |folder: &[Mail]| -> Vec<String> { folder.iter().map(|mail| mail.id.clone()).collect() };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![
+        mail("1", None),
+        mail("2", None),
+        mail("3", Some("1")),
+        mail("5", Some("4")),
+      ],
+      sent: vec![mail("4", Some("3")), mail("6", Some("2"))],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let accounts = vec![account.clone(), owner_account.clone()];
+
+    assert_eq!(
+      Processor::process_delete_thread(&accounts, "7", true, &program_id),
+      Err(MailNotFound.into())
+    );
+    assert_eq!(
+      Processor::process_delete_thread(&[account.clone(), account.clone()], "1", true, &program_id),
+      Err(Unauthorized.into())
+    );
+
+    Processor::process_delete_thread(&accounts, "1", false, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(ids(&mail_account.inbox), vec!["2"]);
+    assert_eq!(ids(&mail_account.sent), vec!["4", "6"]);
+
+    Processor::process_delete_thread(&accounts, "2", true, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert!(mail_account.inbox.is_empty());
+    assert_eq!(ids(&mail_account.sent), vec!["4"]);
+  }
+
+  #[test]
+  fn test_too_few_accounts() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![account];
+
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(NotEnoughAccounts.into())
+    );
+    assert_eq!(
+      Processor::process_init_account(&accounts, None, &program_id),
+      Err(NotEnoughAccounts.into())
+    );
+    assert_eq!(
+      Processor::process_init_account(&[], None, &program_id),
+      Err(NotEnoughAccounts.into())
+    );
+  }
+
+  #[test]
+  fn test_export_mailbox() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::default();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 1000];
+    let mut small_lamports = 0;
+    let mut small_data = [0; 50];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let small_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut small_lamports,
+      &mut small_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      subject: String::from("Hey Mike!!!"),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2")],
+      sent: vec![mail("3")],
+      contacts: vec![(String::from("mike"), Pubkey::new_unique().to_string())],
+      owner_pubkey: Pubkey::new_unique().to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), small_account.clone()];
+    assert_eq!(
+      Processor::process_export_mailbox(&accounts, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+    Processor::process_export_mailbox(&accounts, &program_id).unwrap();
+
+    let exported = Processor::load_mail_account(&scratch_account).unwrap();
+    assert_eq!(exported.inbox, mail_account.inbox);
+    assert_eq!(exported.sent, mail_account.sent);
+    assert_eq!(exported.contacts, mail_account.contacts);
+    assert_eq!(exported.owner_pubkey, mail_account.owner_pubkey);
+  }
+
+  #[test]
+  fn test_scratch_must_sign() {
+    let program_id = Pubkey::default();
+    let key = Pubkey::new_unique();
+    let victim_key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let mut victim_lamports = 0;
+    let mut victim_data = [0; 1000];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let victim_account = AccountInfo::new(
+      &victim_key,
+      false,
+      true,
+      &mut victim_lamports,
+      &mut victim_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: vec![Mail {
+        id: String::from("1"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+    Processor::store_mail_account(&victim_account, &mut MailAccount::default()).unwrap();
+    let victim_before = victim_account.data.borrow().to_vec();
+
+    let accounts = vec![mail_account_info.clone(), victim_account.clone()];
+    let results = vec![
+      Processor::process_get_stats(&accounts, &program_id),
+      Processor::process_get_unread_count(&accounts, &program_id),
+      Processor::process_get_storage_usage(&accounts, &program_id),
+      Processor::process_get_message(&accounts, "1", &program_id),
+      Processor::process_get_message_by_index(&accounts, Folder::Inbox, 0, &program_id),
+      Processor::process_get_inbox_page(&accounts, 0, 1, &program_id),
+      Processor::process_get_folder(&accounts, Folder::Inbox, 0, 1, &program_id),
+      Processor::process_search_mailbox(&accounts, "", &program_id),
+      Processor::process_export_mailbox(&accounts, &program_id),
+    ];
+    for result in results {
+      assert_eq!(result, Err(Unauthorized.into()));
+    }
+    assert_eq!(*victim_account.data.borrow(), &victim_before[..]);
+  }
+
+  #[test]
+  fn test_import_mailbox() {
+    let program_id = Pubkey::default();
+    let backup_key = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let mut backup_lamports = 0;
+    let mut backup_data = [0; 1000];
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let backup_account = AccountInfo::new(
+      &backup_key,
+      false,
+      false,
+      &mut backup_lamports,
+      &mut backup_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+    let ids =
+      |folder: &[Mail]| -> Vec<String> { folder.iter().map(|mail| mail.id.clone()).collect() };
+
+    let current = || MailAccount {
+      inbox: vec![mail("1"), mail("2")],
+      owner_pubkey: owner_key.to_string(),
+      send_nonce: 5,
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(
+      &backup_account,
+      &mut MailAccount {
+        inbox: vec![mail("2"), mail("3")],
+        sent: vec![mail("4")],
+        owner_pubkey: Pubkey::new_unique().to_string(),
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    let accounts = vec![
+      backup_account.clone(),
+      mail_account_info.clone(),
+      owner_account.clone(),
+    ];
+
+    Processor::store_mail_account(&mail_account_info, &mut current()).unwrap();
+    Processor::process_import_mailbox(&accounts, true, &program_id).unwrap();
+
+    let merged = Processor::load_mail_account(&mail_account_info).unwrap();
+    assert_eq!(ids(&merged.inbox), vec!["1", "2", "3"]);
+    assert_eq!(ids(&merged.sent), vec!["4"]);
+
+    Processor::store_mail_account(&mail_account_info, &mut current()).unwrap();
+    Processor::process_import_mailbox(&accounts, false, &program_id).unwrap();
+
+    let restored = Processor::load_mail_account(&mail_account_info).unwrap();
+    assert_eq!(ids(&restored.inbox), vec!["2", "3"]);
+    assert_eq!(ids(&restored.sent), vec!["4"]);
+    assert_eq!(restored.owner_pubkey, owner_key.to_string());
+    assert_eq!(restored.send_nonce, 5);
+
+    // A corrupt backup is rejected before the mailbox is written.
+    backup_account.data.borrow_mut()[4..].fill(0xff);
+    let before = mail_account_info.data.borrow().to_vec();
+    assert!(Processor::process_import_mailbox(&accounts, false, &program_id).is_err());
+    assert_eq!(*mail_account_info.data.borrow(), &before[..]);
+  }
+
+  #[test]
+  fn test_process_scheduled() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        allowlist_only: true,
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Later"),
+      ..Mail::default()
+    };
+
+    let schedule_accounts = vec![sender_account.clone(), owner_account.clone()];
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_schedule_mail(
+        &[sender_account.clone(), unsigned_owner],
+        mail("due"),
+        NOW - 1,
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+
+    let forged = Mail {
+      from_address: receiver_key.to_string(),
+      ..mail("due")
+    };
+    assert_eq!(
+      Processor::process_schedule_mail(&schedule_accounts, forged, NOW - 1, &program_id),
+      Err(SenderMismatch.into())
+    );
+
+    let unaddressed = Mail {
+      to_address: String::from("nobody"),
+      ..mail("due")
+    };
+    assert_eq!(
+      Processor::process_schedule_mail(&schedule_accounts, unaddressed, NOW - 1, &program_id),
+      Err(InvalidAddress.into())
+    );
+
+    Processor::process_schedule_mail(&schedule_accounts, mail("due"), NOW - 1, &program_id)
+      .unwrap();
+    Processor::process_schedule_mail(&schedule_accounts, mail("later"), NOW + 60, &program_id)
+      .unwrap();
+    assert!(Processor::load_mail_account(&receiver_account)
+      .unwrap()
+      .inbox
+      .is_empty());
+
+    let accounts = vec![sender_account.clone()];
+    assert_eq!(
+      Processor::process_scheduled(&accounts, &program_id),
+      Err(NotEnoughAccounts.into())
+    );
+
+    // A mismatched receiver, or one that refuses the mail, leaves it queued.
+    let queued = vec![(mail("due"), NOW - 1), (mail("later"), NOW + 60)];
+    for accounts in vec![
+      vec![sender_account.clone(), sender_account.clone()],
+      vec![sender_account.clone(), receiver_account.clone()],
+    ] {
+      Processor::process_scheduled(&accounts, &program_id).unwrap();
+      let sender = Processor::load_mail_account(&sender_account).unwrap();
+      assert_eq!(sender.scheduled, queued);
+      assert!(sender.sent.is_empty());
+    }
+
+    let mut receiver = Processor::load_mail_account(&receiver_account).unwrap();
+    receiver.allowlist_only = false;
+    Processor::store_mail_account(&receiver_account, &mut receiver).unwrap();
+
+    let accounts = vec![sender_account.clone(), receiver_account.clone()];
+    Processor::process_scheduled(&accounts, &program_id).unwrap();
+
+    let receiver = Processor::load_mail_account(&receiver_account).unwrap();
+    assert_eq!(receiver.inbox, vec![delivered(mail("due"))]);
+
+    let sender = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(sender.sent, vec![stamped(mail("due"))]);
+    assert_eq!(sender.scheduled, vec![(mail("later"), NOW + 60)]);
+
+    Processor::process_scheduled(&accounts, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_process_scheduled_caps_due_mails() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = vec![0; 8000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = vec![0; 8000];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      false,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: usize| Mail {
+      id: id.to_string(),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      ..Mail::default()
+    };
+
+    let mut sender = MailAccount {
+      scheduled: (0..=MAX_RECIPIENTS).map(|id| (mail(id), NOW)).collect(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut sender).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut MailAccount::default()).unwrap();
+
+    let mut accounts = vec![sender_account.clone()];
+    accounts.extend(vec![receiver_account.clone(); MAX_RECIPIENTS + 1]);
+    Processor::process_scheduled(&accounts, &program_id).unwrap();
+
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      MAX_RECIPIENTS
+    );
+    assert_eq!(
+      Processor::load_mail_account(&sender_account)
+        .unwrap()
+        .scheduled,
+      vec![(mail(MAX_RECIPIENTS), NOW)]
+    );
+  }
+
+  #[test]
+  fn test_cancel_scheduled() {
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      false,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      ..Mail::default()
+    };
+
+    let mut sender = MailAccount {
+      scheduled: vec![(mail("1"), NOW), (mail("2"), NOW + 60)],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut sender).unwrap();
+
+    let accounts = vec![sender_account.clone(), owner_account.clone()];
+
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_cancel_scheduled(
+        &[sender_account.clone(), unsigned_owner],
+        "1",
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+    assert_eq!(
+      Processor::process_cancel_scheduled(&accounts, "3", &program_id),
+      Err(MailNotFound.into())
+    );
+
+    Processor::process_cancel_scheduled(&accounts, "1", &program_id).unwrap();
+
+    assert_eq!(
+      Processor::load_mail_account(&sender_account)
+        .unwrap()
+        .scheduled,
+      vec![(mail("2"), NOW + 60)]
+    );
+  }
+
+  #[test]
+  fn test_send_mail_logs_free_space() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike"),
+      body: String::from("Body text"),
+      ..Mail::default()
+    };
+    let needed = usize::try_from(delivered(mail.clone()).byte_size).unwrap();
+    let empty_len = 4 + get_instance_packed_len(&MailAccount::default()).unwrap();
+
+    let mut sender_lamports = 0;
+    let mut sender_data = vec![0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = vec![0; empty_len + needed - 1];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut MailAccount::default()).unwrap();
+
+    LOGS.with(|logs| logs.borrow_mut().clear());
+
+    let accounts = vec![sender_account, receiver_account, owner_account];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(AccountTooSmall.into())
+    );
+
+    let expected = format!(
+      "AccountTooSmall: {} needs {} bytes, {} available",
+      receiver_key,
+      needed,
+      needed - 1
+    );
+    LOGS.with(|logs| assert_eq!(*logs.borrow(), vec![expected]));
+  }
+
+  #[test]
+  fn test_send_mail_keep_sent() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(&accounts, &mail("1"), false, 0, false, false, &program_id)
+      .unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&sender_account).unwrap().sent,
+      vec![stamped(mail("1"))]
+    );
+
+    let settings = vec![sender_account.clone(), owner_account.clone()];
+    let mut unsigned_owner = owner_account.clone();
+    unsigned_owner.is_signer = false;
+    assert_eq!(
+      Processor::process_set_keep_sent(
+        &[sender_account.clone(), unsigned_owner],
+        false,
+        &program_id
+      ),
+      Err(Unauthorized.into())
+    );
+    Processor::process_set_keep_sent(&settings, false, &program_id).unwrap();
+    Processor::process_send_mail(&accounts, &mail("2"), false, 1, false, false, &program_id)
+      .unwrap();
+
+    let sender = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(sender.sent, vec![stamped(mail("1"))]);
+    assert!(!sender.keep_sent);
+    assert_eq!(
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .len(),
+      2
+    );
+  }
+
+  #[test]
+  fn test_get_unread_count() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 8];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      read,
+      ..Mail::default()
+    };
+
+    let cases = [
+      (vec![mail("1", true), mail("2", true)], 0),
+      (
+        vec![mail("1", false), mail("2", false), mail("3", false)],
+        3,
+      ),
+      (vec![mail("1", false), mail("2", true), mail("3", false)], 2),
+    ];
+
+    for (inbox, unread) in cases.iter() {
+      let mut mail_account = MailAccount {
+        inbox: inbox.clone(),
+        ..MailAccount::default()
+      };
+      Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+      let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+      Processor::process_get_unread_count(&accounts, &program_id).unwrap();
+      assert_eq!(
+        Processor::load_with_header::<u32>(&scratch_account).unwrap(),
+        *unread
+      );
+
+      LOGS.with(|logs| logs.borrow_mut().clear());
+      let accounts = vec![mail_account_info.clone()];
+      Processor::process_get_unread_count(&accounts, &program_id).unwrap();
+      LOGS.with(|logs| {
+        assert_eq!(
+          *logs.borrow(),
+          vec![
+            format!("UNREAD {}", unread),
+            format!("OK GetUnreadCount {}", mail_account_info.key)
+          ]
+        )
+      });
+    }
+  }
+
+  #[test]
+  fn test_write_account_serialization_failed() {
+    stub_clock();
+
+    /// Fails to serialize, as a value too large for borsh's length prefixes would.
+    struct Oversized;
+    impl BorshSerialize for Oversized {
+      fn serialize<W: std::io::Write>(&self, _writer: &mut W) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+          std::io::ErrorKind::InvalidInput,
+          "length does not fit in u32",
+        ))
+      }
+    }
+
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 64];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    LOGS.with(|logs| logs.borrow_mut().clear());
+
+    assert_eq!(
+      Processor::write_account(&account, &Oversized),
+      Err(SerializationFailed.into())
+    );
+    assert_eq!(*account.data.borrow(), &[0; 64][..]);
+    LOGS.with(|logs| {
+      assert_eq!(
+        *logs.borrow(),
+        vec![format!("SerializationFailed: {}", key)]
+      )
+    });
+  }
+
+  #[test]
+  fn test_send_batch_best_effort() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let other_program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let valid_key = Pubkey::new_unique();
+    let invalid_key = Pubkey::new_unique();
+    let fresh_key = Pubkey::new_unique();
+    let scratch_key = Pubkey::new_unique();
+    let full_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut valid_lamports = 0;
+    let mut valid_data = [0; 1000];
+    let mut invalid_lamports = 0;
+    let mut invalid_data = [0; 1000];
+    let mut fresh_lamports = 0;
+    let mut fresh_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 400];
+    let mut full_lamports = 0;
+    let mut full_data = vec![0; 4 + get_instance_packed_len(&MailAccount::default()).unwrap()];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let valid_account = AccountInfo::new(
+      &valid_key,
+      false,
+      true,
+      &mut valid_lamports,
+      &mut valid_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    // Owned by another program, so it cannot take mail.
+    let invalid_account = AccountInfo::new(
+      &invalid_key,
+      false,
+      true,
+      &mut invalid_lamports,
+      &mut invalid_data,
+      &other_program_id,
+      false,
+      Epoch::default(),
+    );
+
+    // Owned by this program but never initialized.
+    let fresh_account = AccountInfo::new(
+      &fresh_key,
+      false,
+      true,
+      &mut fresh_lamports,
+      &mut fresh_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    // Initialized, but with no room left for any mail.
+    let full_account = AccountInfo::new(
+      &full_key,
+      false,
+      true,
+      &mut full_lamports,
+      &mut full_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &scratch_key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&valid_account, &mut MailAccount::default()).unwrap();
+    Processor::store_mail_account(&full_account, &mut MailAccount::default()).unwrap();
+    let full_before = full_account.data.borrow().to_vec();
+
+    let mail = |id: &str, to: &Pubkey| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: to.to_string(),
+      subject: String::from("Hey"),
+      ..Mail::default()
+    };
+    let mails = vec![mail("1", &valid_key), mail("2", &invalid_key)];
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      valid_account.clone(),
+      invalid_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_batch(&accounts, &mails, &program_id),
+      Err(ProgramError::IncorrectProgramId)
+    );
+
+    let accounts = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      valid_account.clone(),
+      invalid_account.clone(),
+      fresh_account.clone(),
+      valid_account.clone(),
+      full_account.clone(),
+      scratch_account.clone(),
+    ];
+    let best_effort_mails = vec![
+      mails[0].clone(),
+      mails[1].clone(),
+      mail("3", &fresh_key),
+      mail("4", &fresh_key),
+      mail("5", &full_key),
+    ];
+    Processor::process_send_batch_best_effort(&accounts, &best_effort_mails, &program_id).unwrap();
+
+    assert_eq!(
+      Processor::load_with_header::<Vec<(String, bool)>>(&scratch_account).unwrap(),
+      vec![
+        (valid_key.to_string(), true),
+        (invalid_key.to_string(), false),
+        (fresh_key.to_string(), false),
+        (valid_key.to_string(), false),
+        (full_key.to_string(), false),
+      ]
+    );
+    assert_eq!(
+      Processor::load_mail_account(&valid_account).unwrap().inbox,
+      vec![delivered(mails[0].clone())]
+    );
+    assert_eq!(
+      Processor::load_mail_account(&sender_account).unwrap().sent,
+      vec![stamped(mails[0].clone())]
+    );
+    assert_eq!(*invalid_account.data.borrow(), &[0; 1000][..]);
+    assert_eq!(*fresh_account.data.borrow(), &[0; 1000][..]);
+    assert_eq!(*full_account.data.borrow(), &full_before[..]);
+  }
+
+  #[test]
+  fn test_get_folder() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 2000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 1000];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("in")],
+      sent: vec![mail("s1"), mail("s2"), mail("s3")],
+      archive: vec![mail("a1"), mail("a2")],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info, scratch_account.clone()];
+    let page = |folder: Folder, start: u32, count: u32| {
+      Processor::process_get_folder(&accounts, folder, start, count, &program_id).unwrap();
+      Processor::load_with_header::<Page>(&scratch_account).unwrap()
+    };
+
+    assert_eq!(
+      page(Folder::Sent, 1, 5),
+      Page {
+        total: 3,
+        next_offset: 3,
+        mails: vec![mail("s2"), mail("s3")],
+      }
+    );
+    assert_eq!(
+      page(Folder::Archive, 0, 1),
+      Page {
+        total: 2,
+        next_offset: 1,
+        mails: vec![mail("a1")],
+      }
+    );
+    assert_eq!(
+      page(Folder::Archive, 7, 1),
+      Page {
+        total: 2,
+        next_offset: 2,
+        mails: Vec::new(),
+      }
+    );
+  }
+
+  #[test]
+  fn test_set_profile() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+
+    let profile = Profile {
+      display_name: String::from("Mike"),
+      avatar_uri: String::from("ipfs://avatar"),
+    };
+
+    let accounts = vec![account.clone(), account.clone()];
+    assert_eq!(
+      Processor::process_set_profile(&accounts, Some(profile.clone()), &program_id),
+      Err(Unauthorized.into())
+    );
+
+    let accounts = vec![account.clone(), owner_account];
+    let overlong = |display_name: usize, avatar_uri: usize| Profile {
+      display_name: "n".repeat(display_name),
+      avatar_uri: "u".repeat(avatar_uri),
+    };
+    assert_eq!(
+      Processor::process_set_profile(
+        &accounts,
+        Some(overlong(MAX_DISPLAY_NAME_LEN + 1, 1)),
+        &program_id
+      ),
+      Err(DisplayNameTooLong.into())
+    );
+    assert_eq!(
+      Processor::process_set_profile(
+        &accounts,
+        Some(overlong(1, MAX_AVATAR_URI_LEN + 1)),
+        &program_id
+      ),
+      Err(AvatarUriTooLong.into())
+    );
+
+    Processor::process_set_profile(&accounts, Some(profile.clone()), &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().profile,
+      Some(profile)
+    );
+
+    Processor::process_set_profile(&accounts, None, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().profile,
+      None
+    );
+  }
+
+  #[test]
+  fn test_get_storage_usage() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 8];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2")],
+      archive: vec![mail("3")],
+      contacts: vec![(String::from("mike"), key.to_string())],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+    Processor::process_get_storage_usage(&accounts, &program_id).unwrap();
+
+    let total_bytes = Processor::load_with_header::<u32>(&scratch_account).unwrap();
+    assert_eq!(total_bytes, mail_account.total_bytes());
+
+    let data_length = DataLength::try_from_slice(&mail_account_info.data.borrow()[..4]).unwrap();
+    assert!(total_bytes < data_length.length);
+    assert_eq!(
+      data_length.length - total_bytes,
+      u32::try_from(
+        get_instance_packed_len(&MailAccount {
+          contacts: mail_account.contacts.clone(),
+          ..MailAccount::default()
+        })
+        .unwrap()
+          - 5 * 4
+      )
+      .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_send_mail_idempotency_key() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 2000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 2000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut sender = MailAccount {
+      recent_keys: (0..MAX_RECENT_KEYS).map(|i| format!("k{}", i)).collect(),
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&sender_account, &mut sender).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let mail = |id: &str, key: &str| Mail {
+      id: String::from(id),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey"),
+      idempotency_key: String::from(key),
+      ..Mail::default()
+    };
+    let inbox_ids = || -> Vec<String> {
+      Processor::load_mail_account(&receiver_account)
+        .unwrap()
+        .inbox
+        .iter()
+        .map(|mail| mail.id.clone())
+        .collect()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    Processor::process_send_mail(
+      &accounts,
+      &mail("1", "new"),
+      false,
+      0,
+      false,
+      false,
+      &program_id,
+    )
+    .unwrap();
+    assert_eq!(inbox_ids(), vec!["1"]);
+
+    // A retry under a fresh id and the stale nonce is skipped, not rejected.
+    Processor::process_send_mail(
+      &accounts,
+      &mail("2", "new"),
+      false,
+      0,
+      false,
+      false,
+      &program_id,
+    )
+    .unwrap();
+    assert_eq!(inbox_ids(), vec!["1"]);
+
+    let sender = Processor::load_mail_account(&sender_account).unwrap();
+    assert_eq!(sender.send_nonce, 1);
+    assert_eq!(sender.recent_keys.len(), MAX_RECENT_KEYS);
+    assert_eq!(sender.recent_keys[0], "k1");
+    assert_eq!(sender.recent_keys.last().unwrap(), "new");
+
+    // The oldest key was evicted, so it is accepted again.
+    Processor::process_send_mail(
+      &accounts,
+      &mail("3", "k0"),
+      false,
+      1,
+      false,
+      false,
+      &program_id,
+    )
+    .unwrap();
+    assert_eq!(inbox_ids(), vec!["1", "3"]);
+  }
+
+  #[test]
+  fn test_add_reaction() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let reactor_key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 3000];
+    let mut reactor_lamports = 0;
+    let mut reactor_data = [0; 0];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let reactor_account = AccountInfo::new(
+      &reactor_key,
+      true,
+      false,
+      &mut reactor_lamports,
+      &mut reactor_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      sent: vec![Mail {
+        id: String::from("1"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+
+    let reactions = || {
+      Processor::load_mail_account(&account).unwrap().sent[0]
+        .reactions
+        .clone()
+    };
+
+    let accounts = vec![account.clone(), account.clone()];
+    assert_eq!(
+      Processor::process_add_reaction(&accounts, "1", String::from("👍"), &program_id),
+      Err(Unauthorized.into())
+    );
+
+    let accounts = vec![account.clone(), reactor_account];
+    assert_eq!(
+      Processor::process_add_reaction(&accounts, "2", String::from("👍"), &program_id),
+      Err(MailNotFound.into())
+    );
+
+    Processor::process_add_reaction(&accounts, "1", String::from("👍"), &program_id).unwrap();
+    assert_eq!(
+      reactions(),
+      vec![(reactor_key.to_string(), String::from("👍"))]
+    );
+    let reacted = Processor::load_mail_account(&account).unwrap().sent[0].clone();
+    assert_eq!(reacted.byte_size, stamped(reacted.clone()).byte_size);
+
+    Processor::process_add_reaction(&accounts, "1", String::from("🎉"), &program_id).unwrap();
+    assert_eq!(
+      reactions(),
+      vec![(reactor_key.to_string(), String::from("🎉"))]
+    );
+
+    let mut mail_account = Processor::load_mail_account(&account).unwrap();
+    mail_account.sent[0].reactions = (0..MAX_REACTIONS)
+      .map(|i| (format!("reactor-{}", i), String::from("👀")))
+      .collect();
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+
+    assert_eq!(
+      Processor::process_add_reaction(&accounts, "1", String::from("👍"), &program_id),
+      Err(TooManyReactions.into())
+    );
+  }
+
+  #[test]
+  fn test_search_mailbox() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 200];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str, subject: &str, body: &str| Mail {
+      id: String::from(id),
+      subject: String::from(subject),
+      body: String::from(body),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![
+        mail("1", "Your INVOICE", "Due soon"),
+        mail("2", "Lunch?", "Noon"),
+      ],
+      sent: vec![
+        mail("3", "Re: lunch", "The invoice is paid"),
+        mail("4", "Hello", "Nothing here"),
+      ],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info.clone(), scratch_account.clone()];
+    Processor::process_search_mailbox(&accounts, "Invoice", &program_id).unwrap();
+
+    assert_eq!(
+      Processor::load_with_header::<Vec<(Folder, String)>>(&scratch_account).unwrap(),
+      vec![
+        (Folder::Inbox, String::from("1")),
+        (Folder::Sent, String::from("3"))
+      ]
+    );
+
+    Processor::process_search_mailbox(&accounts, "missing", &program_id).unwrap();
+    assert!(
+      Processor::load_with_header::<Vec<(Folder, String)>>(&scratch_account)
+        .unwrap()
+        .is_empty()
+    );
+  }
+
+  #[test]
+  fn test_write_account_keeps_header_on_failed_body() {
+    /// Writes part of its encoding, then fails.
+    struct FailsMidway;
+    impl BorshSerialize for FailsMidway {
+      fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&[0xff; 8])?;
+        Err(std::io::Error::new(
+          std::io::ErrorKind::WriteZero,
+          "body write failed",
+        ))
+      }
+    }
+
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: vec![Mail {
+        id: String::from("1"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&account, &mut mail_account).unwrap();
+    let before = account.data.borrow().to_vec();
+
+    assert_eq!(
+      Processor::write_account(&account, &FailsMidway),
+      Err(SerializationFailed.into())
+    );
+    assert_eq!(*account.data.borrow(), &before[..]);
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().inbox[0].id,
+      "1"
+    );
+  }
+
+  #[test]
+  fn test_send_mail_recipient_mismatch() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+    let receiver_before = receiver_account.data.borrow().to_vec();
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: Pubkey::new_unique().to_string(),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(RecipientMismatch.into())
+    );
+    assert_eq!(*receiver_account.data.borrow(), &receiver_before[..]);
+    assert!(Processor::load_mail_account(&sender_account)
+      .unwrap()
+      .sent
+      .is_empty());
+  }
+
+  #[test]
+  fn test_send_mail_sender_mismatch() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let contact_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = [0; 1000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = [0; 1000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(
+      &receiver_account,
+      &mut MailAccount {
+        contacts: vec![(String::from("contact"), contact_key.to_string())],
+        allowlist_only: true,
+        ..MailAccount::default()
+      },
+    )
+    .unwrap();
+    let sender_before = sender_account.data.borrow().to_vec();
+    let receiver_before = receiver_account.data.borrow().to_vec();
+
+    let forged = Mail {
+      id: String::from("1"),
+      from_address: contact_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Hey Mike"),
+      ..Mail::default()
+    };
+
+    let accounts = vec![
+      sender_account.clone(),
+      receiver_account.clone(),
+      owner_account.clone(),
+    ];
+    let fan_out = vec![
+      sender_account.clone(),
+      owner_account.clone(),
+      receiver_account.clone(),
+    ];
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &forged, false, 0, false, false, &program_id),
+      Err(SenderMismatch.into())
+    );
+    assert_eq!(
+      Processor::process_send_mail_bcc(&fan_out, &forged, &program_id),
+      Err(SenderMismatch.into())
+    );
+    assert_eq!(
+      Processor::process_send_batch(&fan_out, std::slice::from_ref(&forged), &program_id),
+      Err(SenderMismatch.into())
+    );
+    assert_eq!(*sender_account.data.borrow(), &sender_before[..]);
+    assert_eq!(*receiver_account.data.borrow(), &receiver_before[..]);
+
+    // The allowlist is checked against the sending mailbox, not the address the mail claims.
+    let mail = Mail {
+      from_address: sender_key.to_string(),
+      ..forged
+    };
+    assert_eq!(
+      Processor::process_send_mail(&accounts, &mail, false, 0, false, false, &program_id),
+      Err(SenderNotAllowlisted.into())
+    );
+  }
+
+  #[test]
+  fn test_limits_enforced_at_boundary() {
+    use crate::error::MailError::{AttachmentUriTooLong, TooManyAttachments};
+    use crate::limits::{MAX_ATTACHMENTS, MAX_ATTACHMENT_URI_LEN, MAX_SEARCH_RESULTS};
+
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let sender_key = Pubkey::new_unique();
+    let receiver_key = Pubkey::new_unique();
+    let mut sender_lamports = 0;
+    let mut sender_data = vec![0; 8000];
+    let mut receiver_lamports = 0;
+    let mut receiver_data = vec![0; 8000];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let sender_account = AccountInfo::new(
+      &sender_key,
+      true,
+      true,
+      &mut sender_lamports,
+      &mut sender_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let receiver_account = AccountInfo::new(
+      &receiver_key,
+      false,
+      true,
+      &mut receiver_lamports,
+      &mut receiver_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    Processor::store_mail_account(&sender_account, &mut owned_by(&owner_key)).unwrap();
+    Processor::store_mail_account(&receiver_account, &mut owned_by(&owner_key)).unwrap();
+
+    let accounts = vec![sender_account, receiver_account, owner_account];
+    let validate = |mail: Mail| Processor::process_validate_send(&accounts, &mail, &program_id);
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: sender_key.to_string(),
+      to_address: receiver_key.to_string(),
+      subject: String::from("Limits"),
+      ..Mail::default()
+    };
+
+    let priority = |priority: u8| Mail {
+      priority,
+      ..mail.clone()
+    };
+    assert_eq!(validate(priority(MAX_PRIORITY)), Ok(()));
+    assert_eq!(
+      validate(priority(MAX_PRIORITY + 1)),
+      Err(InvalidPriority.into())
+    );
+
+    let subject = |len: usize| Mail {
+      subject: "s".repeat(len),
+      ..mail.clone()
+    };
+    assert_eq!(validate(subject(MAX_SUBJECT_LEN)), Ok(()));
+    assert_eq!(
+      validate(subject(MAX_SUBJECT_LEN + 1)),
+      Err(SubjectTooLong.into())
+    );
+
+    let labels = |count: usize| Mail {
+      labels: (0..count).map(|i| i.to_string()).collect(),
+      ..mail.clone()
+    };
+    assert_eq!(validate(labels(MAX_LABELS)), Ok(()));
+    assert_eq!(validate(labels(MAX_LABELS + 1)), Err(TooManyLabels.into()));
+
+    let attachments = |count: usize, len: usize| Mail {
+      attachments: vec!["u".repeat(len); count],
+      ..mail.clone()
+    };
+    assert_eq!(
+      validate(attachments(MAX_ATTACHMENTS, MAX_ATTACHMENT_URI_LEN)),
+      Ok(())
+    );
+    assert_eq!(
+      validate(attachments(MAX_ATTACHMENTS + 1, 1)),
+      Err(TooManyAttachments.into())
+    );
+    assert_eq!(
+      validate(attachments(1, MAX_ATTACHMENT_URI_LEN + 1)),
+      Err(AttachmentUriTooLong.into())
+    );
+
+    assert_eq!(Processor::check_recipient_count(MAX_RECIPIENTS), Ok(()));
+    assert_eq!(
+      Processor::check_recipient_count(MAX_RECIPIENTS + 1),
+      Err(TooManyRecipients.into())
+    );
+
+    let quota = |bytes_used: u32| {
+      let mut mail_account = MailAccount {
+        bytes_used,
+        ..MailAccount::default()
+      };
+      Mailboxes::charge(&mut mail_account, &stamped(mail.clone()))
+    };
+    let mail_size = stamped(mail.clone()).byte_size;
+    assert_eq!(quota(MAX_ACCOUNT_BYTES - mail_size), Ok(()));
+    assert_eq!(
+      quota(MAX_ACCOUNT_BYTES - mail_size + 1),
+      Err(QuotaExceeded.into())
+    );
+
+    let profile = |name_len: usize, uri_len: usize| Profile {
+      display_name: "n".repeat(name_len),
+      avatar_uri: "u".repeat(uri_len),
+    };
+    assert_eq!(
+      profile(MAX_DISPLAY_NAME_LEN, MAX_AVATAR_URI_LEN).validate(),
+      Ok(())
+    );
+    assert_eq!(
+      profile(MAX_DISPLAY_NAME_LEN + 1, 0).validate(),
+      Err(DisplayNameTooLong)
+    );
+    assert_eq!(
+      profile(0, MAX_AVATAR_URI_LEN + 1).validate(),
+      Err(AvatarUriTooLong)
+    );
+
+    let mut reacted = mail.clone();
+    for i in 0..MAX_REACTIONS {
+      reacted.react(i.to_string(), String::from("+1")).unwrap();
+    }
+    assert_eq!(
+      reacted.react(String::from("last"), String::from("+1")),
+      Err(TooManyReactions)
+    );
+
+    let searched = MailAccount {
+      inbox: vec![mail.clone(); MAX_SEARCH_RESULTS + 1],
+      ..MailAccount::default()
+    };
+    assert_eq!(searched.search("limits").len(), MAX_SEARCH_RESULTS);
+
+    let mut remembering = MailAccount::default();
+    for i in 0..=MAX_RECENT_KEYS {
+      remembering.record_key(i.to_string());
+    }
+    assert_eq!(remembering.recent_keys.len(), MAX_RECENT_KEYS);
+    assert_eq!(remembering.recent_keys[0], "1");
+
+    let pending = |id: &str| Mail {
+      id: String::from(id),
+      ..mail.clone()
+    };
+    let mut sender = Processor::load_mail_account(&accounts[0]).unwrap();
+    sender.pending = vec![
+      (pending("edge"), NOW - UNDO_WINDOW_SECS),
+      (pending("late"), NOW - UNDO_WINDOW_SECS - 1),
+    ];
+    Processor::store_mail_account(&accounts[0], &mut sender).unwrap();
+    let mut receiver = MailAccount {
+      inbox: vec![pending("edge"), pending("late")],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&accounts[1], &mut receiver).unwrap();
+    assert_eq!(
+      Processor::process_undo_send(&accounts, "edge", &program_id),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::process_undo_send(&accounts, "late", &program_id),
+      Err(UndoWindowExpired.into())
+    );
+  }
+
+  #[test]
+  fn test_get_message_by_index() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut mail_lamports = 0;
+    let mut mail_data = [0; 1000];
+    let mut scratch_lamports = 0;
+    let mut scratch_data = [0; 200];
+
+    let mail_account_info = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut mail_lamports,
+      &mut mail_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let scratch_account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut scratch_lamports,
+      &mut scratch_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("in")],
+      sent: vec![mail("s1"), mail("s2")],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&mail_account_info, &mut mail_account).unwrap();
+
+    let accounts = vec![mail_account_info, scratch_account.clone()];
+    Processor::process_get_message_by_index(&accounts, Folder::Sent, 1, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_with_header::<Mail>(&scratch_account).unwrap(),
+      mail("s2")
+    );
+
+    assert_eq!(
+      Processor::process_get_message_by_index(&accounts, Folder::Sent, 2, &program_id),
+      Err(IndexOutOfBounds.into())
+    );
+    assert_eq!(
+      Processor::process_get_message_by_index(&accounts, Folder::Archive, 0, &program_id),
+      Err(IndexOutOfBounds.into())
+    );
+  }
+
+  #[test]
+  fn test_send_mail_bcc_delivers_in_account_order() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    let mut lamports = vec![0; keys.len()];
+    let mut data = vec![[0; 1000]; keys.len()];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut accounts: Vec<AccountInfo> = keys
+      .iter()
+      .zip(lamports.iter_mut())
+      .zip(data.iter_mut())
+      .map(|((key, lamports), data)| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          data,
+          &program_id,
+          false,
+          Epoch::default(),
+        )
+      })
+      .collect();
+    accounts.insert(1, owner_account);
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: keys[0].to_string(),
+      subject: String::from("Hey everyone"),
+      ..Mail::default()
+    };
+
+    Processor::store_mail_account(&accounts[0], &mut owned_by(&owner_key)).unwrap();
+    for account in &accounts[2..] {
+      Processor::store_mail_account(account, &mut MailAccount::default()).unwrap();
+    }
+    Processor::process_send_mail_bcc(&accounts, &mail, &program_id).unwrap();
+
+    let receivers: Vec<String> = keys[1..].iter().map(|key| key.to_string()).collect();
+
+    for (account, key) in accounts[2..].iter().zip(&receivers) {
+      let inbox = Processor::load_mail_account(account).unwrap().inbox;
+      assert_eq!(inbox.len(), 1);
+      assert_eq!(inbox[0].to_address, *key);
+    }
+
+    let sent = Processor::load_mail_account(&accounts[0]).unwrap().sent;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].bcc, receivers);
+  }
+
+  #[test]
+  fn test_reply_all() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let mut lamports = vec![0; keys.len()];
+    let mut data = vec![[0; 1000]; keys.len()];
+    let owner_key = Pubkey::new_unique();
+    let mut owner_lamports = 0;
+    let mut owner_data = [0; 0];
+
+    let owner_account = AccountInfo::new(
+      &owner_key,
+      true,
+      false,
+      &mut owner_lamports,
+      &mut owner_data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut accounts: Vec<AccountInfo> = keys
+      .iter()
+      .zip(lamports.iter_mut())
+      .zip(data.iter_mut())
+      .map(|((key, lamports), data)| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          data,
+          &program_id,
+          false,
+          Epoch::default(),
+        )
+      })
+      .collect();
+    accounts.insert(1, owner_account);
+
+    // The replier received the original alongside a second participant.
+    let original = Mail {
+      id: String::from("1"),
+      from_address: keys[1].to_string(),
+      to_address: keys[0].to_string(),
+      bcc: vec![keys[0].to_string(), keys[2].to_string()],
+      ..Mail::default()
+    };
+    let replier = MailAccount {
+      inbox: vec![original.clone()],
+      owner_pubkey: owner_key.to_string(),
+      ..MailAccount::default()
+    };
+    Processor::write_account(&accounts[0], &replier).unwrap();
+    let mut author = MailAccount {
+      sent: vec![original.clone()],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&accounts[2], &mut author).unwrap();
+    let mut participant = MailAccount {
+      inbox: vec![original],
+      ..MailAccount::default()
+    };
+    Processor::store_mail_account(&accounts[3], &mut participant).unwrap();
+
+    let reply = Mail {
+      id: String::from("2"),
+      from_address: keys[0].to_string(),
+      subject: String::from("Re: Plans"),
+      in_reply_to: Some(String::from("1")),
+      ..Mail::default()
+    };
+
+    let reordered = vec![
+      accounts[0].clone(),
+      accounts[1].clone(),
+      accounts[3].clone(),
+      accounts[2].clone(),
+    ];
+    assert_eq!(
+      Processor::process_reply_all(&reordered, &reply, &program_id),
+      Err(ParticipantMismatch.into())
+    );
+    assert_eq!(
+      Processor::process_reply_all(&accounts[..3], &reply, &program_id),
+      Err(ParticipantMismatch.into())
+    );
+
+    let unknown = Mail {
+      in_reply_to: Some(String::from("9")),
+      ..reply.clone()
+    };
+    assert_eq!(
+      Processor::process_reply_all(&accounts, &unknown, &program_id),
+      Err(MailNotFound.into())
+    );
+
+    Processor::process_reply_all(&accounts, &reply, &program_id).unwrap();
+
+    for account in &accounts[2..] {
+      let inbox = Processor::load_mail_account(account).unwrap().inbox;
+      let received = inbox.last().unwrap();
+      assert_eq!(received.id, "2");
+      assert_eq!(received.to_address, account.key.to_string());
+      assert_eq!(received.in_reply_to, Some(String::from("1")));
+    }
+
+    let sent = Processor::load_mail_account(&accounts[0]).unwrap().sent;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].bcc, vec![keys[1].to_string(), keys[2].to_string()]);
+  }
+
+  #[test]
+  #[cfg(feature = "strict")]
+  fn test_store_mail_account_strict() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mut mail_account = MailAccount {
+      inbox: vec![Mail {
+        from_address: String::from("mike"),
+        ..Mail::default()
+      }],
+      ..MailAccount::default()
+    };
+    assert_eq!(
+      Processor::store_mail_account(&account, &mut mail_account),
+      Err(InvalidAddress.into())
+    );
+    assert_eq!(MailboxHeader::read(&account).unwrap().length, 0);
+  }
+
+  #[test]
+  fn test_migrate_dates_legacy_layout() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let legacy = LegacyMailAccount {
+      inbox: vec![LegacyMail {
+        id: String::from("1"),
+        subject: String::from("Hey Mike"),
+        sent_date: String::from("9/29/2021, 3:58:02 PM"),
+        ..LegacyMail::default()
+      }],
+      sent: Vec::new(),
+    };
+    Processor::write_account(&account, &legacy).unwrap();
+
+    assert!(Processor::load_mail_account(&account).is_err());
+
+    Processor::process_migrate_dates(&account, &program_id).unwrap();
+
+    let mail_account = Processor::load_mail_account(&account).unwrap();
+    assert_eq!(mail_account.inbox.len(), 1);
+    assert_eq!(mail_account.inbox[0].subject, "Hey Mike");
+    assert_eq!(mail_account.inbox[0].sent_at, 1_632_931_082);
+    assert!(mail_account.sent.is_empty());
+    assert!(mail_account.keep_sent);
+
+    // Migrating again reads the current layout and changes nothing.
+    Processor::process_migrate_dates(&account, &program_id).unwrap();
+    assert_eq!(
+      Processor::load_mail_account(&account).unwrap().inbox.len(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_short_account_lists_do_not_panic() {
+    stub_clock();
+
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 1000];
+
+    let account = AccountInfo::new(
+      &key,
+      true,
+      true,
+      &mut lamports,
+      &mut data,
+      &program_id,
+      false,
+      Epoch::default(),
+    );
+
+    let mail = Mail {
+      id: String::from("1"),
+      from_address: key.to_string(),
+      to_address: key.to_string(),
+      subject: String::from("Hey Mike!!!"),
+      in_reply_to: Some(String::from("1")),
+      ..Mail::default()
+    };
+    let id = String::from("1");
+    let address = key.to_string();
+
+    let payloads: Vec<Vec<u8>> = vec![
+      Vec::new(),
+      (mail.clone(), false, 0u64, false, false)
+        .try_to_vec()
+        .unwrap(),
+      (id.clone(), mail.clone()).try_to_vec().unwrap(),
+      Vec::new(),
+      Vec::new(),
+      (String::from("me"), address.clone()).try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      Vec::new(),
+      mail.try_to_vec().unwrap(),
+      (id.clone(), id.clone()).try_to_vec().unwrap(),
+      (id.clone(), id.clone()).try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      address.try_to_vec().unwrap(),
+      (vec![mail.clone()], false).try_to_vec().unwrap(),
+      0u32.try_to_vec().unwrap(),
+      (id.clone(), Folder::Inbox, Folder::Archive)
+        .try_to_vec()
+        .unwrap(),
+      mail.try_to_vec().unwrap(),
+      Vec::new(),
+      id.try_to_vec().unwrap(),
+      Vec::new(),
+      id.try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      Vec::new(),
+      (id.clone(), id.clone()).try_to_vec().unwrap(),
+      true.try_to_vec().unwrap(),
+      mail.try_to_vec().unwrap(),
+      (0u32, 1u32).try_to_vec().unwrap(),
+      Vec::new(),
+      Vec::new(),
+      id.try_to_vec().unwrap(),
+      Some(1u32).try_to_vec().unwrap(),
+      Vec::new(),
+      (id.clone(), id.clone(), address.clone(), false)
+        .try_to_vec()
+        .unwrap(),
+      false.try_to_vec().unwrap(),
+      Some(1u64).try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      (id.clone(), false).try_to_vec().unwrap(),
+      Vec::new(),
+      false.try_to_vec().unwrap(),
+      (mail.clone(), 0u64).try_to_vec().unwrap(),
+      Vec::new(),
+      true.try_to_vec().unwrap(),
+      Vec::new(),
+      (Folder::Inbox, 0u32, 1u32).try_to_vec().unwrap(),
+      None::<Profile>.try_to_vec().unwrap(),
+      Vec::new(),
+      (id.clone(), String::from("+1")).try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+      (Folder::Inbox, 0u32).try_to_vec().unwrap(),
+      mail.try_to_vec().unwrap(),
+      vec![address.clone()].try_to_vec().unwrap(),
+      mail.try_to_vec().unwrap(),
+      id.try_to_vec().unwrap(),
+    ];
+
+    for (tag, payload) in payloads.into_iter().enumerate() {
+      let mut instruction_data = vec![u8::try_from(tag).unwrap()];
+      instruction_data.extend(payload);
+      assert!(
+        MailInstruction::unpack(&instruction_data).is_ok(),
+        "tag {}",
+        tag
+      );
+
+      assert!(
+        Processor::process(&program_id, &[], &instruction_data).is_err(),
+        "tag {}",
+        tag
+      );
+      // Enough for single-account instructions and too few for the rest; neither may panic.
+      let _ = Processor::process(
+        &program_id,
+        std::slice::from_ref(&account),
+        &instruction_data,
+      );
+    }
   }
 }
@@ -1,6 +1,28 @@
+use crate::error::MailError;
+use crate::limits::{
+  MAX_ACCOUNT_BYTES, MAX_ATTACHMENTS, MAX_ATTACHMENT_URI_LEN, MAX_AVATAR_URI_LEN,
+  MAX_DISPLAY_NAME_LEN, MAX_LABELS, MAX_PRIORITY, MAX_REACTIONS, MAX_RECENT_KEYS,
+  MAX_SEARCH_RESULTS, MAX_SUBJECT_LEN,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+  account_info::AccountInfo, borsh::get_instance_packed_len, program_error::ProgramError,
+  pubkey::Pubkey,
+};
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
 
-#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+/// Seed prefix of the mailbox address derived for an owner.
+pub const MAILBOX_SEED: &[u8] = b"mailbox";
+
+/// The program-derived mailbox address of `owner` and its bump seed.
+pub fn find_mailbox_address(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[MAILBOX_SEED, owner.as_ref()], program_id)
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default, PartialEq)]
 pub struct Mail {
   pub id: String,
   pub from_address: String,
@@ -8,12 +30,902 @@ pub struct Mail {
   pub subject: String,
   pub body: String,
   pub sent_date: String,
+  pub in_reply_to: Option<String>,
+  pub read: bool,
+  pub priority: u8,
+  /// Unix timestamp at which the mail expires; `None` never expires.
+  pub expires_at: Option<u64>,
+  /// Every recipient of a blind-copied mail, only kept on the sender's copy.
+  pub bcc: Vec<String>,
+  pub labels: Vec<String>,
+  /// Whether `body` holds a compressed payload. The program treats it as opaque.
+  pub compressed: bool,
+  /// Off-chain content URIs (e.g. IPFS or Arweave), stored as given.
+  pub attachments: Vec<String>,
+  /// Packed size of the mail in bytes, recorded by the program when it is sent.
+  pub byte_size: u32,
+  /// Unix timestamp the mail was sent at; `0` when unknown.
+  pub sent_at: u64,
+  /// Unix timestamp the program delivered the receiver's copy, set from the cluster clock.
+  pub delivered_at: u64,
+  /// Ed25519 signature by the sender's owner wallet over `signing_message`, checked when sent with
+  /// `verify_signature` set; empty for unsigned mail.
+  pub signature: Vec<u8>,
+  /// Set once the receiver acknowledges the mail with a signed `AcknowledgeMail`.
+  pub acknowledged: bool,
+  /// Client-chosen key identifying a send across retries; `SendMail` skips a key the sender
+  /// used recently. Empty opts out.
+  pub idempotency_key: String,
+  /// Reactions as `(reactor address, emoji)` pairs, at most one per address.
+  pub reactions: Vec<(String, String)>,
+}
+
+impl Mail {
+  /// Check the sender-controlled fields before the mail is delivered.
+  pub fn validate(&self) -> Result<(), MailError> {
+    if self.priority > MAX_PRIORITY {
+      return Err(MailError::InvalidPriority);
+    }
+
+    if self.subject.is_empty() && self.body.is_empty() {
+      return Err(MailError::EmptyMessage);
+    }
+
+    if self.subject.len() > MAX_SUBJECT_LEN {
+      return Err(MailError::SubjectTooLong);
+    }
+
+    if self.compressed && self.body.is_empty() {
+      return Err(MailError::EmptyCompressedBody);
+    }
+
+    if self.labels.len() > MAX_LABELS {
+      return Err(MailError::TooManyLabels);
+    }
+
+    if self.attachments.len() > MAX_ATTACHMENTS {
+      return Err(MailError::TooManyAttachments);
+    }
+
+    if self
+      .attachments
+      .iter()
+      .any(|uri| uri.len() > MAX_ATTACHMENT_URI_LEN)
+    {
+      return Err(MailError::AttachmentUriTooLong);
+    }
+
+    Ok(())
+  }
+
+  /// The bytes a sender signs: the borsh encoding of the id, addresses, subject, body and
+  /// sent date.
+  pub fn signing_message(&self) -> Vec<u8> {
+    (
+      &self.id,
+      &self.from_address,
+      &self.to_address,
+      &self.subject,
+      &self.body,
+      &self.sent_date,
+    )
+      .try_to_vec()
+      .unwrap()
+  }
+
+  /// When the mail was sent: `sent_at`, or `sent_date` parsed for mail stored before that
+  /// was recorded. `0` when neither is known.
+  pub fn sent_timestamp(&self) -> u64 {
+    if self.sent_at != 0 {
+      self.sent_at
+    } else {
+      parse_sent_date(&self.sent_date).unwrap_or(0)
+    }
+  }
+
+  /// When the mailbox got the mail: `delivered_at`, or the sender's `sent_at` for mail
+  /// delivered before that was recorded. `0` when neither is known.
+  pub fn received_at(&self) -> u64 {
+    if self.delivered_at != 0 {
+      self.delivered_at
+    } else {
+      self.sent_at
+    }
+  }
+
+  /// Record the mail's packed size in `byte_size`. The field has a fixed width, so setting
+  /// it does not change the size being recorded.
+  pub fn stamp_byte_size(&mut self) {
+    self.byte_size = u32::try_from(get_instance_packed_len(self).unwrap()).unwrap();
+  }
+
+  /// Record `reactor`'s reaction, replacing any earlier one from the same address.
+  pub fn react(&mut self, reactor: String, emoji: String) -> Result<(), MailError> {
+    if let Some(reaction) = self.reactions.iter_mut().find(|(from, _)| *from == reactor) {
+      reaction.1 = emoji;
+      return Ok(());
+    }
+
+    if self.reactions.len() >= MAX_REACTIONS {
+      return Err(MailError::TooManyReactions);
+    }
+
+    self.reactions.push((reactor, emoji));
+
+    Ok(())
+  }
+
+  /// Everyone on the mail in the order `from_address`, `to_address`, then `bcc`, without
+  /// repeats or empty addresses, leaving out `exclude`.
+  pub fn participants(&self, exclude: &str) -> Vec<String> {
+    let mut participants: Vec<String> = Vec::new();
+
+    for address in [&self.from_address, &self.to_address]
+      .iter()
+      .copied()
+      .chain(self.bcc.iter())
+    {
+      if !address.is_empty() && address != exclude && !participants.contains(address) {
+        participants.push(address.clone());
+      }
+    }
+
+    participants
+  }
+
+  /// Whether the mail has an expiry at or before `now`.
+  pub fn is_expired(&self, now: u64) -> bool {
+    matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+  }
+
+  /// The first `max_len` characters of the body, never splitting a multibyte character.
+  pub fn preview(&self, max_len: usize) -> String {
+    match self.body.char_indices().nth(max_len) {
+      Some((end, _)) => self.body[..end].to_string(),
+      None => self.body.clone(),
+    }
+  }
+}
+
+/// Parse a legacy `sent_date` such as `"9/29/2021, 3:58:02 PM"` into Unix seconds, read as UTC.
+pub fn parse_sent_date(sent_date: &str) -> Option<u64> {
+  let (date, time) = sent_date.split_once(", ")?;
+  let (time, meridiem) = time.split_once(' ')?;
+
+  let mut date_parts = date.split('/').map(|part| part.parse::<u64>().ok());
+  let month = date_parts.next()??;
+  let day = date_parts.next()??;
+  let year = date_parts.next()??;
+
+  let mut time_parts = time.split(':').map(|part| part.parse::<u64>().ok());
+  let hour = time_parts.next()??;
+  let minute = time_parts.next()??;
+  let second = time_parts.next()??;
+
+  if date_parts.next().is_some() || time_parts.next().is_some() {
+    return None;
+  }
+
+  if !(1..=12).contains(&month)
+    || !(1..=days_in_month(year, month)).contains(&day)
+    || year < 1970
+    || !(1..=12).contains(&hour)
+    || minute > 59
+    || second > 59
+  {
+    return None;
+  }
+
+  let hour = match meridiem {
+    "AM" => hour % 12,
+    "PM" => hour % 12 + 12,
+    _ => return None,
+  };
+
+  let days = (1970..year).map(days_in_year).sum::<u64>()
+    + (1..month).map(|m| days_in_month(year, m)).sum::<u64>()
+    + day
+    - 1;
+
+  Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+fn is_leap_year(year: u64) -> bool {
+  (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_year(year: u64) -> u64 {
+  if is_leap_year(year) {
+    366
+  } else {
+    365
+  }
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+  match month {
+    2 if is_leap_year(year) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+/// The mail folders of a [MailAccount](struct.MailAccount.html).
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum Folder {
+  Inbox,
+  Sent,
+  Archive,
+  Drafts,
+  Trash,
+}
+
+impl Folder {
+  /// Every folder, in the order inbox, sent, archive, drafts, trash.
+  pub const ALL: [Folder; 5] = [
+    Folder::Inbox,
+    Folder::Sent,
+    Folder::Archive,
+    Folder::Drafts,
+    Folder::Trash,
+  ];
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Debug)]
 pub struct MailAccount {
   pub inbox: Vec<Mail>,
   pub sent: Vec<Mail>,
+  pub drafts: Vec<Mail>,
+  /// Saved contacts as `(alias, address)` pairs.
+  pub contacts: Vec<(String, String)>,
+  pub archive: Vec<Mail>,
+  /// Recently sent mail with its send timestamp, kept while it can still be undone.
+  pub pending: Vec<(Mail, u64)>,
+  /// Address allowed to manage the mailbox, independent of the program that owns the account.
+  pub owner_pubkey: String,
+  /// Position of the last inbox mail the owner has read, clamped to the inbox on every store.
+  pub last_read_index: u32,
+  /// Nonce the next `SendMail` from this account must carry. Other sends leave it alone.
+  pub send_nonce: u64,
+  /// Packed size of the mailbox in bytes, refreshed whenever it is stored.
+  pub bytes_used: u32,
+  /// Deleted mail, kept until the trash is emptied.
+  pub trash: Vec<Mail>,
+  /// Only accept mail from addresses saved in `contacts`.
+  pub allowlist_only: bool,
+  /// Age in days after which `AutoArchive` moves read inbox mail to the archive; `None`
+  /// leaves the inbox alone.
+  pub auto_archive_days: Option<u32>,
+  /// Longest time in seconds an inbox message is kept; older ones are dropped whenever new
+  /// mail is delivered. `None` keeps everything.
+  pub max_age_secs: Option<u64>,
+  /// Mail queued by `ScheduleMail`, with the unix time from which `ProcessScheduled` may
+  /// deliver it.
+  pub scheduled: Vec<(Mail, u64)>,
+  /// Store a copy of each sent mail in `sent`. Senders that do not want to pay rent for the
+  /// folder can turn it off.
+  pub keep_sent: bool,
+  /// How the owner presents themselves to anyone reading the mailbox.
+  pub profile: Option<Profile>,
+  /// Idempotency keys of the latest sends, oldest first, at most `MAX_RECENT_KEYS` of them.
+  pub recent_keys: Vec<String>,
+}
+
+impl Default for MailAccount {
+  fn default() -> Self {
+    MailAccount {
+      inbox: Vec::new(),
+      sent: Vec::new(),
+      drafts: Vec::new(),
+      contacts: Vec::new(),
+      archive: Vec::new(),
+      pending: Vec::new(),
+      owner_pubkey: String::new(),
+      last_read_index: 0,
+      send_nonce: 0,
+      bytes_used: 0,
+      trash: Vec::new(),
+      allowlist_only: false,
+      auto_archive_days: None,
+      max_age_secs: None,
+      scheduled: Vec::new(),
+      keep_sent: true,
+      profile: None,
+      recent_keys: Vec::new(),
+    }
+  }
+}
+
+impl MailAccount {
+  /// The messages stored in `folder`.
+  pub fn folder(&self, folder: Folder) -> &Vec<Mail> {
+    match folder {
+      Folder::Inbox => &self.inbox,
+      Folder::Sent => &self.sent,
+      Folder::Archive => &self.archive,
+      Folder::Drafts => &self.drafts,
+      Folder::Trash => &self.trash,
+    }
+  }
+
+  /// The first folder holding a mail with `id`, checked in the order inbox, sent, archive,
+  /// drafts, trash.
+  pub fn contains_id(&self, id: &str) -> Option<Folder> {
+    Folder::ALL
+      .iter()
+      .copied()
+      .find(|folder| self.folder(*folder).iter().any(|mail| mail.id == id))
+  }
+
+  /// Ensure no mail in `folder` has `id` yet, before one with it is added there.
+  pub fn check_new_id(&self, folder: Folder, id: &str) -> Result<(), MailError> {
+    if self.folder(folder).iter().any(|mail| mail.id == id) {
+      return Err(MailError::DuplicateMailId);
+    }
+
+    Ok(())
+  }
+
+  /// Clear `in_reply_to` on every mail whose parent is no longer stored in any folder, such
+  /// as a reply to mail that was purged or never kept here.
+  pub fn unlink_dangling_replies(&mut self) {
+    let ids: BTreeSet<String> = Folder::ALL
+      .iter()
+      .flat_map(|folder| self.folder(*folder).iter().map(|mail| mail.id.clone()))
+      .collect();
+
+    for folder in Folder::ALL.iter() {
+      for mail in self.folder_mut(*folder) {
+        if matches!(&mail.in_reply_to, Some(parent) if !ids.contains(parent)) {
+          mail.in_reply_to = None;
+        }
+      }
+    }
+  }
+
+  /// Pull `last_read_index` back onto the last inbox mail once the inbox has shrunk below
+  /// it, or to `0` when the inbox is empty.
+  pub fn clamp_read_cursor(&mut self) {
+    let last = u32::try_from(self.inbox.len().saturating_sub(1)).unwrap_or(u32::MAX);
+    self.last_read_index = self.last_read_index.min(last);
+  }
+
+  /// The folder and id of up to `MAX_SEARCH_RESULTS` mails whose subject or body contains
+  /// `query`, ignoring case, in folder order.
+  pub fn search(&self, query: &str) -> Vec<(Folder, String)> {
+    let query = query.to_lowercase();
+
+    Folder::ALL
+      .iter()
+      .flat_map(|folder| self.folder(*folder).iter().map(move |mail| (*folder, mail)))
+      .filter(|(_, mail)| {
+        mail.subject.to_lowercase().contains(&query) || mail.body.to_lowercase().contains(&query)
+      })
+      .map(|(folder, mail)| (folder, mail.id.clone()))
+      .take(MAX_SEARCH_RESULTS)
+      .collect()
+  }
+
+  /// Remember an idempotency key, forgetting the oldest once `MAX_RECENT_KEYS` are held.
+  pub fn record_key(&mut self, key: String) {
+    self.recent_keys.push(key);
+
+    if self.recent_keys.len() > MAX_RECENT_KEYS {
+      let excess = self.recent_keys.len() - MAX_RECENT_KEYS;
+      self.recent_keys.drain(..excess);
+    }
+  }
+
+  /// Packed size of the five folders, including their length prefixes. The rest of the
+  /// stored mailbox is contacts, queues and settings.
+  pub fn total_bytes(&self) -> u32 {
+    Folder::ALL
+      .iter()
+      .map(|folder| get_instance_packed_len(self.folder(*folder)).unwrap())
+      .sum::<usize>() as u32
+  }
+
+  /// Drop inbox messages received before `cutoff`. Messages without a `received_at` are
+  /// kept.
+  pub fn prune_inbox_before(&mut self, cutoff: u64) {
+    self
+      .inbox
+      .retain(|mail| mail.received_at() == 0 || mail.received_at() >= cutoff);
+  }
+
+  /// Append the messages of each of `other`'s folders to the same folder here, skipping any
+  /// whose id is already stored in this mailbox.
+  pub fn merge_from(&mut self, other: MailAccount) {
+    let folders = vec![
+      (Folder::Inbox, other.inbox),
+      (Folder::Sent, other.sent),
+      (Folder::Archive, other.archive),
+      (Folder::Drafts, other.drafts),
+      (Folder::Trash, other.trash),
+    ];
+
+    for (folder, mails) in folders {
+      for mail in mails {
+        if self.contains_id(&mail.id).is_none() {
+          self.folder_mut(folder).push(mail);
+        }
+      }
+    }
+  }
+
+  /// Mutable access to the messages stored in `folder`.
+  pub fn folder_mut(&mut self, folder: Folder) -> &mut Vec<Mail> {
+    match folder {
+      Folder::Inbox => &mut self.inbox,
+      Folder::Sent => &mut self.sent,
+      Folder::Archive => &mut self.archive,
+      Folder::Drafts => &mut self.drafts,
+      Folder::Trash => &mut self.trash,
+    }
+  }
+
+  /// Fill in `sent_at` from `sent_date` for every stored mail that has no timestamp yet.
+  /// Dates that fail to parse are left at `0`.
+  pub fn migrate_dates(&mut self) {
+    let folders = self
+      .inbox
+      .iter_mut()
+      .chain(self.sent.iter_mut())
+      .chain(self.drafts.iter_mut())
+      .chain(self.archive.iter_mut())
+      .chain(self.trash.iter_mut())
+      .chain(self.pending.iter_mut().map(|(mail, _)| mail));
+
+    for mail in folders.filter(|mail| mail.sent_at == 0) {
+      mail.sent_at = parse_sent_date(&mail.sent_date).unwrap_or(0);
+    }
+  }
+
+  /// Move the mail with `mail_id` from one folder to the end of another. Fails when the
+  /// other folder already holds a mail with that id.
+  pub fn move_mail(&mut self, mail_id: &str, from: Folder, to: Folder) -> Result<(), MailError> {
+    let position = self
+      .folder(from)
+      .iter()
+      .position(|mail| mail.id == mail_id)
+      .ok_or(MailError::MailNotFound)?;
+    self.check_new_id(to, mail_id)?;
+    let mail = self.folder_mut(from).remove(position);
+
+    self.folder_mut(to).push(mail);
+
+    Ok(())
+  }
+
+  /// Move read inbox messages received at or before `cutoff` to the archive, keeping their
+  /// order. Unread messages, those without a `received_at` and those whose id is already
+  /// archived stay in the inbox.
+  pub fn archive_read_before(&mut self, cutoff: u64) {
+    let archived: BTreeSet<String> = self.archive.iter().map(|mail| mail.id.clone()).collect();
+    let (old, recent) = self.inbox.drain(..).partition(|mail: &Mail| {
+      mail.read
+        && mail.received_at() != 0
+        && mail.received_at() <= cutoff
+        && !archived.contains(&mail.id)
+    });
+
+    self.inbox = recent;
+    self.archive.extend(old);
+  }
+  /// Inbox and sent messages whose subject contains `query`, ignoring ASCII case.
+  pub fn find_by_subject(&self, query: &str) -> Vec<&Mail> {
+    self
+      .inbox
+      .iter()
+      .chain(self.sent.iter())
+      .filter(|mail| contains_ignore_case(&mail.subject, query))
+      .collect()
+  }
+
+  /// Every inbox and sent message in the conversation started by `root_id`, following
+  /// `in_reply_to` links, ordered by `sent_timestamp`. Each id is visited once, so a message
+  /// present in both folders or a cycle of replies does not repeat.
+  pub fn thread_for(&self, root_id: &str) -> Vec<&Mail> {
+    let mut visited = BTreeSet::new();
+    let mut parents = vec![root_id];
+    let mut thread = Vec::new();
+
+    while let Some(parent) = parents.pop() {
+      for mail in self.inbox.iter().chain(self.sent.iter()) {
+        let belongs = mail.id == parent || mail.in_reply_to.as_deref() == Some(parent);
+
+        if belongs && visited.insert(mail.id.as_str()) {
+          thread.push(mail);
+          parents.push(&mail.id);
+        }
+      }
+    }
+
+    thread.sort_by_key(|mail| mail.sent_timestamp());
+    thread
+  }
+
+  /// Inbox messages, most urgent first. Messages of equal priority keep their inbox order.
+  pub fn inbox_sorted_by_priority(&self) -> Vec<&Mail> {
+    let mut inbox: Vec<&Mail> = self.inbox.iter().collect();
+    inbox.sort_by_key(|mail| Reverse(mail.priority));
+    inbox
+  }
+
+  /// Inbox messages, newest `sent_timestamp` first. Messages without a known send time
+  /// come last, and messages sent at the same time keep their inbox order.
+  pub fn inbox_sorted_recent(&self) -> Vec<&Mail> {
+    let mut inbox: Vec<&Mail> = self.inbox.iter().collect();
+    inbox.sort_by_key(|mail| Reverse(mail.sent_timestamp()));
+    inbox
+  }
+
+  /// Whether mail from `address` may be delivered to this mailbox.
+  pub fn accepts_from(&self, address: &str) -> bool {
+    !self.allowlist_only
+      || self
+        .contacts
+        .iter()
+        .any(|(_, contact_address)| contact_address == address)
+  }
+
+  /// Inbox messages not read yet.
+  pub fn unread(&self) -> Vec<&Mail> {
+    self.inbox.iter().filter(|mail| !mail.read).collect()
+  }
+
+  /// Inbox messages whose `from_address` is exactly `address`.
+  pub fn from_sender(&self, address: &str) -> Vec<&Mail> {
+    self
+      .inbox
+      .iter()
+      .filter(|mail| mail.from_address == address)
+      .collect()
+  }
+
+  /// Inbox messages tagged with `label`.
+  pub fn inbox_with_label(&self, label: &str) -> Vec<&Mail> {
+    self
+      .inbox
+      .iter()
+      .filter(|mail| mail.labels.iter().any(|existing| existing == label))
+      .collect()
+  }
+
+  /// Inbox and sent messages with a `sent_timestamp` in `start..=end`, oldest first.
+  /// Messages without a known send time are left out.
+  pub fn messages_between(&self, start: u64, end: u64) -> Vec<&Mail> {
+    let mut messages: Vec<&Mail> = self
+      .inbox
+      .iter()
+      .chain(&self.sent)
+      .filter(|mail| {
+        let sent_at = mail.sent_timestamp();
+        sent_at != 0 && (start..=end).contains(&sent_at)
+      })
+      .collect();
+    messages.sort_by_key(|mail| mail.sent_timestamp());
+    messages
+  }
+
+  /// `(id, subject, preview)` for each inbox message, for listing without full bodies.
+  pub fn inbox_previews(&self, max_len: usize) -> Vec<(String, String, String)> {
+    self
+      .inbox
+      .iter()
+      .map(|mail| (mail.id.clone(), mail.subject.clone(), mail.preview(max_len)))
+      .collect()
+  }
+
+  /// Up to `limit` inbox messages starting at `offset`, with the metadata needed to fetch
+  /// the next page.
+  pub fn inbox_page(&self, offset: u32, limit: u32) -> Page {
+    self.folder_page(Folder::Inbox, offset, limit)
+  }
+
+  /// Up to `limit` messages of `folder` starting at `offset`. An offset past the end yields
+  /// an empty page.
+  pub fn folder_page(&self, folder: Folder, offset: u32, limit: u32) -> Page {
+    let mails = self.folder(folder);
+    let total = mails.len() as u32;
+    let start = offset.min(total);
+    let end = start.saturating_add(limit).min(total);
+
+    Page {
+      total,
+      next_offset: end,
+      mails: mails[start as usize..end as usize].to_vec(),
+    }
+  }
+
+  /// Number of inbox messages not yet read.
+  pub fn unread_count(&self) -> u32 {
+    self.unread().len() as u32
+  }
+
+  /// Folder counts, cheap enough to render badges without shipping the mailbox.
+  pub fn stats(&self) -> MailStats {
+    MailStats {
+      total_inbox: self.inbox.len() as u32,
+      unread: self.unread_count(),
+      sent: self.sent.len() as u32,
+      archived: self.archive.len() as u32,
+      storage_bytes: self
+        .inbox
+        .iter()
+        .chain(self.sent.iter())
+        .chain(self.archive.iter())
+        .chain(self.drafts.iter())
+        .map(|mail| u64::from(mail.byte_size))
+        .sum(),
+    }
+  }
+
+  /// Check the structural invariants every instruction keeps: no id repeats within a
+  /// folder, every `in_reply_to` names a stored mail, the read cursor is on an inbox mail or
+  /// `0`, each non-empty address is a pubkey, and the mailbox and its mails are within their
+  /// caps.
+  pub fn validate(&self) -> Result<(), MailError> {
+    let is_address = |address: &String| address.is_empty() || Pubkey::from_str(address).is_ok();
+    let ids: BTreeSet<&str> = Folder::ALL
+      .iter()
+      .flat_map(|folder| self.folder(*folder).iter().map(|mail| mail.id.as_str()))
+      .collect();
+
+    if usize::try_from(self.last_read_index).unwrap() >= self.inbox.len().max(1) {
+      return Err(MailError::IndexOutOfBounds);
+    }
+
+    if !is_address(&self.owner_pubkey)
+      || !self.contacts.iter().all(|(_, address)| is_address(address))
+    {
+      return Err(MailError::InvalidAddress);
+    }
+
+    for folder in Folder::ALL.iter() {
+      let mut folder_ids = BTreeSet::new();
+
+      for mail in self.folder(*folder) {
+        if !folder_ids.insert(mail.id.as_str()) {
+          return Err(MailError::DuplicateMailId);
+        }
+
+        if matches!(&mail.in_reply_to, Some(parent) if !ids.contains(parent.as_str())) {
+          return Err(MailError::MailNotFound);
+        }
+
+        if ![&mail.from_address, &mail.to_address]
+          .iter()
+          .copied()
+          .chain(mail.bcc.iter())
+          .all(is_address)
+        {
+          return Err(MailError::InvalidAddress);
+        }
+
+        if mail.labels.len() > MAX_LABELS {
+          return Err(MailError::TooManyLabels);
+        }
+
+        if mail.reactions.len() > MAX_REACTIONS {
+          return Err(MailError::TooManyReactions);
+        }
+      }
+    }
+
+    if self.total_bytes() > MAX_ACCOUNT_BYTES || self.recent_keys.len() > MAX_RECENT_KEYS {
+      return Err(MailError::QuotaExceeded);
+    }
+
+    Ok(())
+  }
+}
+
+/// A mailing list. Mail sent to the group is delivered to every member's mailbox.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct GroupAccount {
+  /// Address allowed to change the members, set by the first `SetGroupMembers`.
+  pub owner: String,
+  pub members: Vec<String>,
+}
+
+/// A slice of a folder. On the last page `next_offset` equals `total`.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct Page {
+  pub total: u32,
+  pub next_offset: u32,
+  pub mails: Vec<Mail>,
+}
+
+/// Human-readable details clients show for a mailbox's address.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+  pub display_name: String,
+  pub avatar_uri: String,
+}
+
+impl Profile {
+  /// Ensure both fields are within their length caps.
+  pub fn validate(&self) -> Result<(), MailError> {
+    if self.display_name.len() > MAX_DISPLAY_NAME_LEN {
+      return Err(MailError::DisplayNameTooLong);
+    }
+
+    if self.avatar_uri.len() > MAX_AVATAR_URI_LEN {
+      return Err(MailError::AvatarUriTooLong);
+    }
+
+    Ok(())
+  }
+}
+
+/// Whether a sent mail is in the receiver's inbox, and whether the receiver has read it.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct SentStatus {
+  pub delivered: bool,
+  pub read: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct MailStats {
+  pub total_inbox: u32,
+  pub unread: u32,
+  pub sent: u32,
+  pub archived: u32,
+  /// Sum of the recorded `byte_size` of every stored mail.
+  pub storage_bytes: u64,
+}
+
+/// Text safe to put on a single log line: control characters, including newlines, and the
+/// Unicode line and paragraph separators are escaped, so a sender cannot forge extra lines.
+pub fn sanitize_for_log(text: &str) -> String {
+  let mut sanitized = String::with_capacity(text.len());
+
+  for c in text.chars() {
+    if c.is_control() || c == '\u{2028}' || c == '\u{2029}' {
+      sanitized.extend(c.escape_default());
+    } else {
+      sanitized.push(c);
+    }
+  }
+
+  sanitized
+}
+
+/// Combining marks and the Latin-1 letters they compose with, as `(mark, bases, composed)`.
+#[cfg(feature = "normalize")]
+const COMPOSITIONS: &[(char, &str, &str)] = &[
+  ('\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+  ('\u{301}', "AEIOUYaeiouy", "ÁÉÍÓÚÝáéíóúý"),
+  ('\u{302}', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+  ('\u{303}', "ANOano", "ÃÑÕãñõ"),
+  ('\u{308}', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+  ('\u{30a}', "Aa", "Åå"),
+  ('\u{327}', "Cc", "Çç"),
+];
+
+/// Normalize text for display: drop control characters and invisible formatting characters
+/// such as zero-width spaces and bidi overrides, then compose a letter followed by a
+/// combining mark into its precomposed form. Composition covers the Latin-1 letters, which
+/// matches NFC for them; other combining sequences are kept as they are.
+#[cfg(feature = "normalize")]
+pub fn normalize_text(text: &str) -> String {
+  let mut normalized = String::with_capacity(text.len());
+
+  for c in text
+    .chars()
+    .filter(|c| !c.is_control() && !is_invisible(*c))
+  {
+    match normalized
+      .chars()
+      .next_back()
+      .and_then(|base| compose(base, c))
+    {
+      Some(composed) => {
+        normalized.pop();
+        normalized.push(composed);
+      }
+      None => normalized.push(c),
+    }
+  }
+
+  normalized
+}
+
+/// Zero-width and directional formatting characters that render as nothing.
+#[cfg(feature = "normalize")]
+fn is_invisible(c: char) -> bool {
+  matches!(
+    c,
+    '\u{200b}'..='\u{200f}'
+      | '\u{202a}'..='\u{202e}'
+      | '\u{2060}'..='\u{2064}'
+      | '\u{2066}'..='\u{2069}'
+      | '\u{feff}'
+  )
+}
+
+#[cfg(feature = "normalize")]
+fn compose(base: char, mark: char) -> Option<char> {
+  let (_, bases, composed) = COMPOSITIONS.iter().find(|(known, _, _)| *known == mark)?;
+  let index = bases.chars().position(|known| known == base)?;
+
+  composed.chars().nth(index)
+}
+
+/// Substring search that compares ASCII letters case-insensitively without allocating.
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+  let needle = needle.as_bytes();
+  needle.is_empty()
+    || haystack
+      .as_bytes()
+      .windows(needle.len())
+      .any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// Recommended data length for an account holding `num_messages` mails with bodies of about
+/// `avg_body_len` bytes, including the 4-byte length header. Each message is sized as a
+/// uuid id, two base58 addresses, a 64 character subject and a formatted date.
+pub fn estimate_account_size(num_messages: usize, avg_body_len: usize) -> usize {
+  let typical_mail = Mail {
+    id: "0".repeat(36),
+    from_address: "1".repeat(44),
+    to_address: "1".repeat(44),
+    subject: "s".repeat(64),
+    body: "b".repeat(avg_body_len),
+    sent_date: "0".repeat(23),
+    ..Mail::default()
+  };
+
+  let empty_len = get_instance_packed_len(&MailAccount::default()).unwrap();
+  let mail_len = get_instance_packed_len(&typical_mail).unwrap();
+
+  MailboxHeader::LEN + empty_len + num_messages * mail_len
+}
+
+/// A mail in the layout of the program's first release, before any field was added.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default, PartialEq)]
+pub struct LegacyMail {
+  pub id: String,
+  pub from_address: String,
+  pub to_address: String,
+  pub subject: String,
+  pub body: String,
+  pub sent_date: String,
+}
+
+impl From<LegacyMail> for Mail {
+  fn from(legacy: LegacyMail) -> Self {
+    Mail {
+      id: legacy.id,
+      from_address: legacy.from_address,
+      to_address: legacy.to_address,
+      subject: legacy.subject,
+      body: legacy.body,
+      sent_date: legacy.sent_date,
+      ..Mail::default()
+    }
+  }
+}
+
+/// A mailbox in the layout of the program's first release, holding only an inbox and a sent
+/// folder. It does not decode as a [MailAccount](struct.MailAccount.html) until
+/// `MigrateDates` upgrades it.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct LegacyMailAccount {
+  pub inbox: Vec<LegacyMail>,
+  pub sent: Vec<LegacyMail>,
+}
+
+impl From<LegacyMailAccount> for MailAccount {
+  fn from(legacy: LegacyMailAccount) -> Self {
+    MailAccount {
+      inbox: legacy.inbox.into_iter().map(Mail::from).collect(),
+      sent: legacy.sent.into_iter().map(Mail::from).collect(),
+      ..MailAccount::default()
+    }
+  }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Debug)]
@@ -21,11 +933,66 @@ pub struct DataLength {
   pub length: u32,
 }
 
+/// The length header at the start of every account the program writes, giving the size of
+/// the serialized value that follows it. A zero length means nothing is stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MailboxHeader {
+  pub length: u32,
+}
+
+impl MailboxHeader {
+  /// Size of the header in bytes; the stored value starts right after it.
+  pub const LEN: usize = 4;
+
+  /// Read the header of `account`.
+  pub fn read(account: &AccountInfo) -> Result<MailboxHeader, ProgramError> {
+    if account.data_len() < Self::LEN {
+      return Err(MailError::AccountTooSmall.into());
+    }
+
+    let data_length = DataLength::try_from_slice(&account.data.borrow()[..Self::LEN])?;
+
+    Ok(MailboxHeader {
+      length: data_length.length,
+    })
+  }
+
+  /// Write a header describing a stored value of `length` bytes.
+  pub fn write(account: &AccountInfo, length: usize) -> Result<(), ProgramError> {
+    if account.data_len() < Self::LEN {
+      return Err(MailError::AccountTooSmall.into());
+    }
+
+    let data_length = DataLength {
+      length: u32::try_from(length).map_err(|_| MailError::ArithmeticOverflow)?,
+    };
+    data_length.serialize(&mut &mut account.data.borrow_mut()[..Self::LEN])?;
+
+    Ok(())
+  }
+
+  /// Whether a value is stored, i.e. the length is non-zero.
+  pub fn is_initialized(&self) -> bool {
+    self.length != 0
+  }
+
+  /// Offset just past the stored value: the header plus `length`. Lengths are `u32` on
+  /// disk, so an end beyond `u32::MAX` overflows.
+  pub fn end(&self) -> Result<usize, ProgramError> {
+    let end = self
+      .length
+      .checked_add(Self::LEN as u32)
+      .ok_or(MailError::ArithmeticOverflow)?;
+
+    Ok(usize::try_from(end).unwrap())
+  }
+}
+
 // Sanity tests
 #[cfg(test)]
 mod test {
   use super::*;
-  use solana_program::{borsh::get_instance_packed_len, pubkey::Pubkey};
+  use solana_program::pubkey::Pubkey;
 
   #[test]
   fn test_mail() {
@@ -36,6 +1003,7 @@ mod test {
       subject: String::from("Hey Mike"),
       body: String::from("Body text with some characters"),
       sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
     };
 
     let mut temp_slice = [0; 500];
@@ -56,11 +1024,12 @@ mod test {
       subject: String::from("Hey Mike"),
       body: String::from("Body text with some characters"),
       sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
     };
 
     let mail_account = MailAccount {
       inbox: vec![mail],
-      sent: Vec::new(),
+      ..MailAccount::default()
     };
 
     let mut temp_slice = [0; 500];
@@ -74,6 +1043,630 @@ mod test {
     assert_eq!(mail_account.inbox[0].subject, "Hey Mike");
   }
 
+  #[test]
+  fn test_validate_empty_message() {
+    let mail = |subject: &str, body: &str| Mail {
+      subject: String::from(subject),
+      body: String::from(body),
+      ..Mail::default()
+    };
+
+    assert_eq!(mail("", "").validate(), Err(MailError::EmptyMessage));
+    assert_eq!(mail("", "Body text").validate(), Ok(()));
+    assert_eq!(mail("Hey Mike", "").validate(), Ok(()));
+  }
+
+  #[test]
+  fn test_validate_compressed() {
+    let mail = |body: &str| Mail {
+      subject: String::from("Hey Mike"),
+      body: String::from(body),
+      compressed: true,
+      ..Mail::default()
+    };
+
+    assert_eq!(mail("").validate(), Err(MailError::EmptyCompressedBody));
+    assert_eq!(mail("80jNyclXyM0vSgEA").validate(), Ok(()));
+  }
+
+  #[test]
+  fn test_mail_attachments() {
+    let mail = Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      subject: String::from("Photos"),
+      attachments: vec![
+        String::from("ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"),
+        String::from("ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U"),
+      ],
+      ..Mail::default()
+    };
+
+    let mut temp_slice = [0; 500];
+
+    mail.serialize(&mut &mut temp_slice[..]).unwrap();
+
+    let deserialized =
+      Mail::try_from_slice(&temp_slice[..get_instance_packed_len(&mail).unwrap()]).unwrap();
+
+    assert_eq!(deserialized.attachments, mail.attachments);
+  }
+
+  #[test]
+  fn test_validate_attachments() {
+    let mail = |count: usize, uri_len: usize| Mail {
+      subject: String::from("Photos"),
+      attachments: vec!["a".repeat(uri_len); count],
+      ..Mail::default()
+    };
+
+    assert_eq!(
+      mail(MAX_ATTACHMENTS, MAX_ATTACHMENT_URI_LEN).validate(),
+      Ok(())
+    );
+    assert_eq!(
+      mail(MAX_ATTACHMENTS + 1, 10).validate(),
+      Err(MailError::TooManyAttachments)
+    );
+    assert_eq!(
+      mail(1, MAX_ATTACHMENT_URI_LEN + 1).validate(),
+      Err(MailError::AttachmentUriTooLong)
+    );
+  }
+
+  #[test]
+  fn test_validate_subject_length() {
+    let mail = |subject_len: usize| Mail {
+      subject: "a".repeat(subject_len),
+      ..Mail::default()
+    };
+
+    assert_eq!(mail(MAX_SUBJECT_LEN).validate(), Ok(()));
+    assert_eq!(
+      mail(MAX_SUBJECT_LEN + 1).validate(),
+      Err(MailError::SubjectTooLong)
+    );
+  }
+
+  #[test]
+  fn test_find_by_subject() {
+    let mail = |subject: &str| Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: Pubkey::default().to_string(),
+      to_address: Pubkey::default().to_string(),
+      subject: String::from(subject),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from("9/29/2021, 3:58:02 PM"),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("Hey Mike"), mail("Lunch on Friday?")],
+      sent: vec![mail("Re: Hey Mike")],
+      ..MailAccount::default()
+    };
+
+    let found = mail_account.find_by_subject("Mike");
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].subject, "Hey Mike");
+    assert_eq!(found[1].subject, "Re: Hey Mike");
+
+    assert!(mail_account.find_by_subject("Invoice").is_empty());
+
+    let found = mail_account.find_by_subject("LUNCH");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].subject, "Lunch on Friday?");
+  }
+
+  #[test]
+  fn test_thread_for() {
+    let mail = |id: &str, in_reply_to: Option<&str>, sent_date: &str| Mail {
+      id: String::from(id),
+      from_address: Pubkey::default().to_string(),
+      to_address: Pubkey::default().to_string(),
+      subject: String::from("Hey Mike"),
+      body: String::from("Body text with some characters"),
+      sent_date: String::from(sent_date),
+      in_reply_to: in_reply_to.map(String::from),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![
+        mail("root", None, "9/29/2021, 1:00:00 PM"),
+        mail("reply-2", Some("reply-1"), "9/29/2021, 3:00:00 PM"),
+        mail("unrelated", None, "9/29/2021, 2:30:00 PM"),
+      ],
+      sent: vec![
+        mail("reply-1", Some("root"), "9/29/2021, 2:00:00 PM"),
+        mail("reply-3", Some("root"), "9/29/2021, 4:00:00 PM"),
+        mail("reply-5", Some("reply-3"), "10/1/2021, 12:00:00 PM"),
+        mail("reply-4", Some("reply-3"), "10/1/2021, 9:00:00 AM"),
+      ],
+      ..MailAccount::default()
+    };
+
+    let ids: Vec<&str> = mail_account
+      .thread_for("root")
+      .iter()
+      .map(|mail| mail.id.as_str())
+      .collect();
+
+    assert_eq!(
+      ids,
+      vec!["root", "reply-1", "reply-2", "reply-3", "reply-4", "reply-5"]
+    );
+    assert!(mail_account.thread_for("missing").is_empty());
+  }
+
+  #[test]
+  fn test_thread_for_cycle() {
+    let mail = |id: &str, in_reply_to: &str| Mail {
+      id: String::from(id),
+      in_reply_to: Some(String::from(in_reply_to)),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("a", "b"), mail("b", "a")],
+      sent: vec![mail("a", "b")],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(mail_account.thread_for("a").len(), 2);
+  }
+
+  #[test]
+  fn test_inbox_sorted_by_priority() {
+    let mail = |id: &str, priority: u8| Mail {
+      id: String::from(id),
+      priority,
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1", 0), mail("2", 2), mail("3", 0), mail("4", 3)],
+      sent: vec![mail("5", 3)],
+      ..MailAccount::default()
+    };
+
+    let ids: Vec<&str> = mail_account
+      .inbox_sorted_by_priority()
+      .iter()
+      .map(|mail| mail.id.as_str())
+      .collect();
+
+    assert_eq!(ids, vec!["4", "2", "1", "3"]);
+  }
+
+  #[test]
+  fn test_inbox_sorted_recent() {
+    let mail = |id: &str, sent_at: u64| Mail {
+      id: String::from(id),
+      sent_at,
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![
+        mail("1", 100),
+        mail("2", 0),
+        mail("3", 300),
+        mail("4", 100),
+        mail("5", 200),
+        Mail {
+          sent_date: String::from("1/1/1970, 12:04:10 AM"),
+          ..mail("7", 0)
+        },
+      ],
+      sent: vec![mail("6", 400)],
+      ..MailAccount::default()
+    };
+
+    let ids: Vec<&str> = mail_account
+      .inbox_sorted_recent()
+      .iter()
+      .map(|mail| mail.id.as_str())
+      .collect();
+
+    assert_eq!(ids, vec!["3", "7", "5", "1", "4", "2"]);
+  }
+
+  #[test]
+  fn test_unread() {
+    let mail = |id: &str, read: bool| Mail {
+      id: String::from(id),
+      read,
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![
+        mail("1", false),
+        mail("2", true),
+        mail("3", false),
+        mail("4", true),
+      ],
+      sent: vec![mail("5", false)],
+      ..MailAccount::default()
+    };
+
+    let ids: Vec<&str> = mail_account
+      .unread()
+      .iter()
+      .map(|mail| mail.id.as_str())
+      .collect();
+
+    assert_eq!(ids, vec!["1", "3"]);
+    assert!(MailAccount::default().unread().is_empty());
+  }
+
+  #[test]
+  fn test_from_sender() {
+    let alice = Pubkey::new_unique().to_string();
+    let bob = Pubkey::new_unique().to_string();
+
+    let mail = |id: &str, from_address: &str| Mail {
+      id: String::from(id),
+      from_address: String::from(from_address),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1", &alice), mail("2", &bob), mail("3", &alice)],
+      sent: vec![mail("4", &alice)],
+      ..MailAccount::default()
+    };
+
+    let ids = |address: &str| -> Vec<String> {
+      mail_account
+        .from_sender(address)
+        .iter()
+        .map(|mail| mail.id.clone())
+        .collect()
+    };
+
+    assert_eq!(ids(&alice), vec!["1", "3"]);
+    assert_eq!(ids(&bob), vec!["2"]);
+    assert!(ids(&alice.to_lowercase()).is_empty());
+    assert!(ids("").is_empty());
+  }
+
+  #[test]
+  fn test_inbox_with_label() {
+    let mail = |id: &str, labels: &[&str]| Mail {
+      id: String::from(id),
+      labels: labels.iter().map(|label| String::from(*label)).collect(),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![
+        mail("1", &["work"]),
+        mail("2", &["family", "work"]),
+        mail("3", &[]),
+      ],
+      sent: vec![mail("4", &["work"])],
+      ..MailAccount::default()
+    };
+
+    let ids: Vec<&str> = mail_account
+      .inbox_with_label("work")
+      .iter()
+      .map(|mail| mail.id.as_str())
+      .collect();
+
+    assert_eq!(ids, vec!["1", "2"]);
+    assert!(mail_account.inbox_with_label("travel").is_empty());
+  }
+
+  #[test]
+  fn test_contains_id() {
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1")],
+      sent: vec![mail("2"), mail("3")],
+      trash: vec![mail("4")],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(mail_account.contains_id("1"), Some(Folder::Inbox));
+    assert_eq!(mail_account.contains_id("3"), Some(Folder::Sent));
+    assert_eq!(mail_account.contains_id("4"), Some(Folder::Trash));
+    assert_eq!(mail_account.contains_id("5"), None);
+  }
+
+  #[test]
+  fn test_messages_between() {
+    let mail = |id: &str, sent_at: u64| Mail {
+      id: String::from(id),
+      sent_at,
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1", 300), mail("2", 100), mail("3", 0)],
+      sent: vec![
+        mail("4", 200),
+        mail("5", 400),
+        Mail {
+          sent_date: String::from("1/1/1970, 12:04:10 AM"),
+          ..mail("6", 0)
+        },
+      ],
+      ..MailAccount::default()
+    };
+
+    let ids = |start, end| -> Vec<String> {
+      mail_account
+        .messages_between(start, end)
+        .iter()
+        .map(|mail| mail.id.clone())
+        .collect()
+    };
+
+    assert_eq!(ids(100, 300), vec!["2", "4", "6", "1"]);
+    assert_eq!(ids(0, u64::MAX), vec!["2", "4", "6", "1", "5"]);
+    assert_eq!(ids(201, 249), Vec::<String>::new());
+    assert_eq!(ids(300, 100), Vec::<String>::new());
+  }
+
+  #[test]
+  fn test_inbox_page() {
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2"), mail("3"), mail("4"), mail("5")],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(
+      mail_account.inbox_page(0, 2),
+      Page {
+        total: 5,
+        next_offset: 2,
+        mails: vec![mail("1"), mail("2")],
+      }
+    );
+    assert_eq!(
+      mail_account.inbox_page(2, 2),
+      Page {
+        total: 5,
+        next_offset: 4,
+        mails: vec![mail("3"), mail("4")],
+      }
+    );
+    assert_eq!(
+      mail_account.inbox_page(4, 2),
+      Page {
+        total: 5,
+        next_offset: 5,
+        mails: vec![mail("5")],
+      }
+    );
+    assert_eq!(
+      mail_account.inbox_page(7, 2),
+      Page {
+        total: 5,
+        next_offset: 5,
+        mails: vec![],
+      }
+    );
+  }
+
+  #[test]
+  fn test_stats() {
+    let mail = |read: bool| Mail {
+      read,
+      byte_size: 100,
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail(true), mail(false), mail(false)],
+      sent: vec![mail(true), mail(true)],
+      archive: vec![mail(true), mail(false), mail(true), mail(true)],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(
+      mail_account.stats(),
+      MailStats {
+        total_inbox: 3,
+        unread: 2,
+        sent: 2,
+        archived: 4,
+        storage_bytes: 900,
+      }
+    );
+    assert_eq!(MailAccount::default().stats(), MailStats::default());
+  }
+
+  #[test]
+  fn test_estimate_account_size() {
+    let mail = |address: String, body_len: usize| Mail {
+      id: String::from("00000000-0000-0000-0000-000000000000"),
+      from_address: address.clone(),
+      to_address: address,
+      subject: "s".repeat(64),
+      body: "b".repeat(body_len),
+      sent_date: String::from("10/14/2021, 12:00:00 PM"),
+      ..Mail::default()
+    };
+
+    assert_eq!(
+      estimate_account_size(0, 0),
+      4 + get_instance_packed_len(&MailAccount::default()).unwrap()
+    );
+
+    let longest_address = "1".repeat(44);
+    let mail_account = MailAccount {
+      inbox: vec![
+        mail(longest_address.clone(), 200),
+        mail(longest_address.clone(), 200),
+      ],
+      sent: vec![mail(longest_address, 200)],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(
+      estimate_account_size(3, 200),
+      4 + get_instance_packed_len(&mail_account).unwrap()
+    );
+
+    let mail_account = MailAccount {
+      inbox: vec![mail(Pubkey::new_unique().to_string(), 200)],
+      sent: vec![mail(Pubkey::new_unique().to_string(), 200)],
+      ..MailAccount::default()
+    };
+
+    assert!(estimate_account_size(2, 200) >= 4 + get_instance_packed_len(&mail_account).unwrap());
+    assert!(estimate_account_size(3, 200) < estimate_account_size(3, 300));
+    assert!(estimate_account_size(3, 200) < estimate_account_size(4, 200));
+  }
+
+  #[test]
+  fn test_move_mail() {
+    let mail = |id: &str| Mail {
+      id: String::from(id),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("1"), mail("2")],
+      drafts: vec![mail("3")],
+      ..MailAccount::default()
+    };
+
+    mail_account
+      .move_mail("1", Folder::Inbox, Folder::Archive)
+      .unwrap();
+    assert_eq!(mail_account.inbox, vec![mail("2")]);
+    assert_eq!(mail_account.archive, vec![mail("1")]);
+
+    mail_account
+      .move_mail("3", Folder::Drafts, Folder::Sent)
+      .unwrap();
+    assert!(mail_account.drafts.is_empty());
+    assert_eq!(mail_account.sent, vec![mail("3")]);
+
+    assert_eq!(
+      mail_account.move_mail("3", Folder::Drafts, Folder::Sent),
+      Err(MailError::MailNotFound)
+    );
+
+    mail_account.inbox.push(mail("1"));
+    assert_eq!(
+      mail_account.move_mail("1", Folder::Inbox, Folder::Archive),
+      Err(MailError::DuplicateMailId)
+    );
+    assert_eq!(mail_account.inbox, vec![mail("2"), mail("1")]);
+  }
+
+  #[test]
+  fn test_preview() {
+    let mail = Mail {
+      body: String::from("Héllo wörld"),
+      ..Mail::default()
+    };
+
+    assert_eq!(mail.preview(0), "");
+    assert_eq!(mail.preview(2), "Hé");
+    assert_eq!(mail.preview(8), "Héllo wö");
+    assert_eq!(mail.preview(100), "Héllo wörld");
+
+    let mail_account = MailAccount {
+      inbox: vec![
+        Mail {
+          id: String::from("1"),
+          subject: String::from("Emoji"),
+          body: String::from("🦀🦀🦀"),
+          ..Mail::default()
+        },
+        Mail {
+          id: String::from("2"),
+          subject: String::from("Short"),
+          body: String::from("Hi"),
+          ..Mail::default()
+        },
+      ],
+      ..MailAccount::default()
+    };
+
+    assert_eq!(
+      mail_account.inbox_previews(2),
+      vec![
+        (
+          String::from("1"),
+          String::from("Emoji"),
+          String::from("🦀🦀")
+        ),
+        (String::from("2"), String::from("Short"), String::from("Hi")),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_sent_date() {
+    assert_eq!(
+      parse_sent_date("9/29/2021, 3:58:02 PM"),
+      Some(1_632_931_082)
+    );
+    assert_eq!(
+      parse_sent_date("10/1/2021, 12:05:09 AM"),
+      Some(1_633_046_709)
+    );
+    assert_eq!(
+      parse_sent_date("2/29/2024, 12:00:00 PM"),
+      Some(1_709_208_000)
+    );
+
+    assert_eq!(parse_sent_date("yesterday"), None);
+    assert_eq!(parse_sent_date("2/30/2021, 1:00:00 PM"), None);
+    assert_eq!(parse_sent_date("9/29/2021, 13:58:02 PM"), None);
+
+    let mail = |sent_date: &str| Mail {
+      sent_date: String::from(sent_date),
+      ..Mail::default()
+    };
+
+    let mut mail_account = MailAccount {
+      inbox: vec![mail("9/29/2021, 3:58:02 PM"), mail("not a date")],
+      sent: vec![mail("10/1/2021, 12:05:09 AM")],
+      ..MailAccount::default()
+    };
+
+    mail_account.migrate_dates();
+
+    assert_eq!(mail_account.inbox[0].sent_at, 1_632_931_082);
+    assert_eq!(mail_account.inbox[1].sent_at, 0);
+    assert_eq!(mail_account.sent[0].sent_at, 1_633_046_709);
+  }
+
+  #[test]
+  fn test_find_mailbox_address() {
+    let program_id = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+
+    let (address, bump) = find_mailbox_address(&owner, &program_id);
+
+    assert_eq!(find_mailbox_address(&owner, &program_id), (address, bump));
+    assert_eq!(
+      Pubkey::create_program_address(&[MAILBOX_SEED, owner.as_ref(), &[bump]], &program_id),
+      Ok(address)
+    );
+    assert_ne!(
+      find_mailbox_address(&Pubkey::new_unique(), &program_id).0,
+      address
+    );
+  }
+
   #[test]
   fn test_data_length() {
     let data_length = DataLength { length: 5 };
@@ -88,4 +1681,201 @@ mod test {
 
     assert_eq!(data_length.length, 5);
   }
+
+  #[test]
+  fn test_total_bytes() {
+    let mail = |id: &str, body: &str| Mail {
+      id: String::from(id),
+      subject: String::from("Hey Mike"),
+      body: String::from(body),
+      ..Mail::default()
+    };
+
+    let mail_account = MailAccount {
+      inbox: vec![mail("1", "Hello"), mail("2", "A longer body")],
+      sent: vec![mail("3", "Sent")],
+      archive: vec![mail("4", "")],
+      trash: vec![mail("5", "Gone")],
+      ..MailAccount::default()
+    };
+
+    let folders = get_instance_packed_len(&mail_account.inbox).unwrap()
+      + get_instance_packed_len(&mail_account.sent).unwrap()
+      + get_instance_packed_len(&mail_account.drafts).unwrap()
+      + get_instance_packed_len(&mail_account.archive).unwrap()
+      + get_instance_packed_len(&mail_account.trash).unwrap();
+    assert_eq!(mail_account.total_bytes() as usize, folders);
+
+    // The remainder is the fixed cost of an empty mailbox beyond its five empty folders.
+    let overhead = get_instance_packed_len(&MailAccount::default()).unwrap() - 5 * 4;
+    assert_eq!(
+      get_instance_packed_len(&mail_account).unwrap() - mail_account.total_bytes() as usize,
+      overhead
+    );
+  }
+
+  #[test]
+  fn test_mailbox_header() {
+    use solana_program::clock::Epoch;
+
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = [0; 16];
+
+    let account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut lamports,
+      &mut data,
+      &owner,
+      false,
+      Epoch::default(),
+    );
+
+    let header = MailboxHeader::read(&account).unwrap();
+    assert!(!header.is_initialized());
+    assert_eq!(header.end().unwrap(), MailboxHeader::LEN);
+
+    MailboxHeader::write(&account, 9).unwrap();
+    assert_eq!(&account.data.borrow()[..MailboxHeader::LEN], &[9, 0, 0, 0]);
+
+    let header = MailboxHeader::read(&account).unwrap();
+    assert_eq!(header, MailboxHeader { length: 9 });
+    assert!(header.is_initialized());
+    assert_eq!(header.end().unwrap(), 13);
+
+    let mut short_lamports = 0;
+    let mut short_data = [0; 3];
+    let short_account = AccountInfo::new(
+      &key,
+      false,
+      true,
+      &mut short_lamports,
+      &mut short_data,
+      &owner,
+      false,
+      Epoch::default(),
+    );
+
+    assert_eq!(
+      MailboxHeader::read(&short_account),
+      Err(MailError::AccountTooSmall.into())
+    );
+    assert_eq!(
+      MailboxHeader::write(&short_account, 1),
+      Err(MailError::AccountTooSmall.into())
+    );
+  }
+
+  #[test]
+  fn test_search_is_bounded() {
+    let mail_account = MailAccount {
+      inbox: (0..MAX_SEARCH_RESULTS + 5)
+        .map(|i| Mail {
+          id: i.to_string(),
+          subject: String::from("Match"),
+          ..Mail::default()
+        })
+        .collect(),
+      ..MailAccount::default()
+    };
+
+    let matches = mail_account.search("match");
+    assert_eq!(matches.len(), MAX_SEARCH_RESULTS);
+    assert_eq!(matches[0], (Folder::Inbox, String::from("0")));
+  }
+
+  #[test]
+  fn test_sanitize_for_log() {
+    assert_eq!(
+      sanitize_for_log("Hi\nOK SendMail FakeKey"),
+      "Hi\\nOK SendMail FakeKey"
+    );
+    assert_eq!(
+      sanitize_for_log("a\r\nb\tc\u{1b}[2J"),
+      "a\\r\\nb\\tc\\u{1b}[2J"
+    );
+    assert_eq!(sanitize_for_log("one\u{2028}two"), "one\\u{2028}two");
+    assert_eq!(sanitize_for_log("Café, 你好"), "Café, 你好");
+  }
+
+  #[cfg(feature = "normalize")]
+  #[test]
+  fn test_normalize_text() {
+    assert_eq!(normalize_text("Cafe\u{301} au lait"), "Café au lait");
+    assert_eq!(normalize_text("n\u{303}o\u{308}"), "ñö");
+    assert_eq!(normalize_text("pay\u{200b}pal\u{feff}"), "paypal");
+    assert_eq!(normalize_text("Invoice\u{202e}fdp.exe"), "Invoicefdp.exe");
+    assert_eq!(normalize_text("line\nbreak\u{7}"), "linebreak");
+    // Marks without a Latin-1 composition are left in place.
+    assert_eq!(normalize_text("x\u{301}"), "x\u{301}");
+    assert_eq!(normalize_text("Already café"), "Already café");
+  }
+
+  #[test]
+  fn test_mail_account_validate() {
+    let alice = Pubkey::new_unique().to_string();
+    let bob = Pubkey::new_unique().to_string();
+
+    let valid = || MailAccount {
+      owner_pubkey: alice.clone(),
+      inbox: vec![Mail {
+        id: String::from("1"),
+        from_address: bob.clone(),
+        to_address: alice.clone(),
+        ..Mail::default()
+      }],
+      sent: vec![Mail {
+        id: String::from("1"),
+        from_address: alice.clone(),
+        to_address: bob.clone(),
+        in_reply_to: Some(String::from("1")),
+        ..Mail::default()
+      }],
+      drafts: vec![Mail {
+        id: String::from("2"),
+        ..Mail::default()
+      }],
+      contacts: vec![(String::from("bob"), bob.clone())],
+      ..MailAccount::default()
+    };
+    assert_eq!(valid().validate(), Ok(()));
+    assert_eq!(MailAccount::default().validate(), Ok(()));
+
+    let mut duplicate = valid();
+    duplicate.inbox.push(duplicate.inbox[0].clone());
+    assert_eq!(duplicate.validate(), Err(MailError::DuplicateMailId));
+
+    let mut dangling = valid();
+    dangling.sent[0].in_reply_to = Some(String::from("9"));
+    assert_eq!(dangling.validate(), Err(MailError::MailNotFound));
+    dangling.unlink_dangling_replies();
+    assert_eq!(dangling.sent[0].in_reply_to, None);
+    assert_eq!(dangling.validate(), Ok(()));
+
+    let mut past_inbox = valid();
+    past_inbox.last_read_index = 1;
+    assert_eq!(past_inbox.validate(), Err(MailError::IndexOutOfBounds));
+    past_inbox.clamp_read_cursor();
+    assert_eq!(past_inbox.last_read_index, 0);
+    assert_eq!(past_inbox.validate(), Ok(()));
+
+    let mut bad_bcc = valid();
+    bad_bcc.sent[0].bcc = vec![String::from("bob")];
+    assert_eq!(bad_bcc.validate(), Err(MailError::InvalidAddress));
+
+    let mut bad_contact = valid();
+    bad_contact.contacts[0].1 = String::from("bob");
+    assert_eq!(bad_contact.validate(), Err(MailError::InvalidAddress));
+
+    let mut labelled = valid();
+    labelled.inbox[0].labels = vec![String::from("work"); MAX_LABELS + 1];
+    assert_eq!(labelled.validate(), Err(MailError::TooManyLabels));
+
+    let mut remembering = valid();
+    remembering.recent_keys = vec![String::from("key"); MAX_RECENT_KEYS + 1];
+    assert_eq!(remembering.validate(), Err(MailError::QuotaExceeded));
+  }
 }